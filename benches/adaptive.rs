@@ -0,0 +1,107 @@
+//! Compare drain latency and wakeups of `notify_adaptive` under bursty load.
+//!
+//! Worker threads wait on the waitlist and process jobs from a shared counter until it is
+//! empty. The producer adds bursts of jobs, calling `notify_adaptive` once per job, and
+//! measures how long each burst takes to drain and how many wakeups it caused.
+
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Wake, Waker};
+use std::thread::{self, Thread};
+use std::time::{Duration, Instant};
+
+use waitlist::{AdaptivePolicy, Waitlist};
+
+const WORKERS: usize = 8;
+const BURSTS: usize = 200;
+const BURST_SIZE: usize = 64;
+
+struct ThreadWaker(Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+struct Shared {
+    waitlist: Waitlist,
+    jobs: AtomicUsize,
+    wakeups: AtomicUsize,
+    stop: AtomicBool,
+}
+
+fn worker(shared: &Shared) {
+    let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+    let cx = Context::from_waker(&waker);
+    let mut handle = shared.waitlist.wait();
+    while !shared.stop.load(Ordering::Acquire) {
+        handle.set_context(&cx);
+        if shared.jobs.load(Ordering::Acquire) == 0 {
+            thread::park_timeout(Duration::from_millis(10));
+        }
+        if !handle.is_pending() || handle.finish() {
+            shared.wakeups.fetch_add(1, Ordering::Relaxed);
+        }
+        while shared
+            .jobs
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| n.checked_sub(1))
+            .is_ok()
+        {
+            // simulate a small amount of work per job
+            for _ in 0..200 {
+                std::hint::spin_loop();
+            }
+        }
+    }
+}
+
+fn run(max_batch: usize) -> (Duration, usize) {
+    let shared = Shared {
+        waitlist: Waitlist::new(),
+        jobs: AtomicUsize::new(0),
+        wakeups: AtomicUsize::new(0),
+        stop: AtomicBool::new(false),
+    };
+    shared.waitlist.set_adaptive_policy(AdaptivePolicy {
+        max_batch,
+        ..AdaptivePolicy::default()
+    });
+    let mut total = Duration::default();
+    thread::scope(|s| {
+        for _ in 0..WORKERS {
+            s.spawn(|| worker(&shared));
+        }
+        for _ in 0..BURSTS {
+            let start = Instant::now();
+            for _ in 0..BURST_SIZE {
+                shared.jobs.fetch_add(1, Ordering::AcqRel);
+                shared.waitlist.notify_adaptive();
+            }
+            while shared.jobs.load(Ordering::Acquire) != 0 {
+                thread::yield_now();
+            }
+            total += start.elapsed();
+        }
+        shared.stop.store(true, Ordering::Release);
+        shared.waitlist.notify_all();
+    });
+    (
+        total / BURSTS as u32,
+        shared.wakeups.load(Ordering::Relaxed),
+    )
+}
+
+fn main() {
+    println!(
+        "{} workers, {} bursts of {} jobs",
+        WORKERS, BURSTS, BURST_SIZE
+    );
+    for &max_batch in &[1, 2, 4, 8] {
+        let (latency, wakeups) = run(max_batch);
+        println!(
+            "max_batch {:>2}: mean drain time {:>10?}, {:>6} wakeups",
+            max_batch, latency, wakeups
+        );
+    }
+}
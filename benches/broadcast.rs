@@ -0,0 +1,67 @@
+//! Measure how long `notify_all` keeps the waitlist locked while waking slow wakers.
+//!
+//! Many waiters are parked with wakers that each take about a microsecond to run, like a
+//! runtime that has to push the task onto a shared run queue, and then all woken with
+//! `notify_all`. Each waker checks with `try_notify_one` whether the waitlist is still
+//! locked, so the time until the first waker finds it unlocked is how long any other task
+//! trying to register or notify would have been blocked.
+
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Wake, Waker};
+use std::time::{Duration, Instant};
+
+use waitlist::{Waitlist, WouldBlock};
+
+const WAITERS: usize = 1_000;
+const ROUNDS: u32 = 50;
+
+struct SlowTask {
+    waitlist: Arc<Waitlist>,
+    unlocked_at: Mutex<Option<Instant>>,
+}
+
+impl Wake for SlowTask {
+    fn wake(self: Arc<Self>) {
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_micros(1) {
+            std::hint::spin_loop();
+        }
+        let mut unlocked_at = self.unlocked_at.lock().unwrap();
+        if unlocked_at.is_none() && self.waitlist.try_notify_one() != Err(WouldBlock) {
+            *unlocked_at = Some(Instant::now());
+        }
+    }
+}
+
+fn main() {
+    let waitlist = Arc::new(Waitlist::new());
+    let task = Arc::new(SlowTask {
+        waitlist: waitlist.clone(),
+        unlocked_at: Mutex::new(None),
+    });
+    let waker = Waker::from(task.clone());
+    let cx = Context::from_waker(&waker);
+
+    let mut broadcast = Duration::ZERO;
+    let mut locked = Duration::ZERO;
+    for _ in 0..ROUNDS {
+        let mut handles: Vec<_> = (0..WAITERS).map(|_| waitlist.wait()).collect();
+        for handle in &mut handles {
+            handle.set_context(&cx);
+        }
+        *task.unlocked_at.lock().unwrap() = None;
+        let start = Instant::now();
+        waitlist.notify_all();
+        let end = Instant::now();
+        let unlocked_at = task.unlocked_at.lock().unwrap().unwrap_or(end);
+        broadcast += end - start;
+        locked += unlocked_at - start;
+        for handle in &mut handles {
+            handle.finish();
+        }
+    }
+
+    println!("notify_all of {} slow wakers", WAITERS);
+    println!("  mean time for the call: {:>10?}", broadcast / ROUNDS);
+    println!("  mean time locked:       {:>10?}", locked / ROUNDS);
+}
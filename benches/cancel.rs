@@ -0,0 +1,72 @@
+//! Measure how the cost of cancelling waits from the middle of the queue grows with the
+//! number of waiting tasks.
+//!
+//! Each round fills a waitlist with `size` waiters, then cancels every other one, starting
+//! from the back so that each cancelled waiter has as many waiters in front of it as
+//! possible, and finally notifies the rest. The same workload is run against a plain
+//! `VecDeque` of keys with linear removal, like the waitlist's queue used to be, as a
+//! baseline. With constant time removal, the time per cancel should stay roughly flat as the
+//! queue grows, while the baseline grows linearly.
+
+use std::collections::VecDeque;
+use std::hint::black_box;
+use std::task::Context;
+use std::time::{Duration, Instant};
+
+use futures_task::noop_waker;
+use waitlist::Waitlist;
+
+const SIZES: [usize; 4] = [16, 256, 4096, 16_384];
+/// The total number of waiters to add for each size, split over as many rounds as needed
+const WAITERS_PER_SIZE: usize = 1 << 16;
+
+fn waitlist_round(size: usize) -> Duration {
+    let waitlist = Waitlist::new();
+    let waker = noop_waker();
+    let cx = Context::from_waker(&waker);
+    let mut handles: Vec<_> = (0..size)
+        .map(|_| {
+            let mut handle = waitlist.wait();
+            handle.set_context(&cx);
+            handle
+        })
+        .collect();
+    let start = Instant::now();
+    for handle in handles.iter_mut().rev().step_by(2) {
+        black_box(handle.cancel());
+    }
+    let elapsed = start.elapsed();
+    waitlist.notify_all();
+    elapsed
+}
+
+fn baseline_round(size: usize) -> Duration {
+    let mut queue: VecDeque<usize> = (0..size).collect();
+    let start = Instant::now();
+    for key in (0..size).rev().step_by(2) {
+        let idx = queue.iter().position(|&k| k == key).unwrap();
+        black_box(queue.remove(idx));
+    }
+    let elapsed = start.elapsed();
+    queue.clear();
+    elapsed
+}
+
+/// The mean time per cancel for `size` waiters.
+fn measure(size: usize, round: fn(usize) -> Duration) -> Duration {
+    let rounds = (WAITERS_PER_SIZE / size).max(1);
+    let total: Duration = (0..rounds).map(|_| round(size)).sum();
+    total / (rounds * size.div_ceil(2)) as u32
+}
+
+fn main() {
+    println!("mean time per cancel, cancelling every other waiter from the back");
+    for &size in &SIZES {
+        println!(
+            "{:>6} waiters: waitlist {:>10?}, linear baseline {:>10?}",
+            size,
+            measure(size, waitlist_round),
+            measure(size, baseline_round)
+        );
+    }
+}
@@ -0,0 +1,53 @@
+//! Measure the cost of notifying a waitlist that many threads use at once.
+//!
+//! Each thread repeatedly registers a waiter, notifies one waiter, and finishes its own wait,
+//! so that every operation contends for the waitlist's lock. Compare the results with and
+//! without the `parking_lot` feature to see the effect of the lock implementation.
+
+use std::hint::black_box;
+use std::task::Context;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use futures_task::noop_waker;
+use waitlist::Waitlist;
+
+const ITERATIONS: usize = 200_000;
+
+fn run(threads: usize) -> Duration {
+    let waitlist = Waitlist::new();
+    let start = Instant::now();
+    thread::scope(|s| {
+        for _ in 0..threads {
+            s.spawn(|| {
+                let waker = noop_waker();
+                let cx = Context::from_waker(&waker);
+                for _ in 0..ITERATIONS {
+                    let mut handle = waitlist.wait();
+                    handle.set_context(&cx);
+                    black_box(waitlist.notify_one());
+                    black_box(handle.finish());
+                }
+            });
+        }
+    });
+    start.elapsed() / (threads * ITERATIONS) as u32
+}
+
+fn main() {
+    println!(
+        "lock: {}",
+        if cfg!(feature = "parking_lot") {
+            "parking_lot::Mutex"
+        } else {
+            "std::sync::Mutex"
+        }
+    );
+    for &threads in &[1, 2, 4, 8] {
+        println!(
+            "{} threads: mean time per iteration {:>10?}",
+            threads,
+            run(threads)
+        );
+    }
+}
@@ -0,0 +1,110 @@
+//! Compare `Waitlist` with the experimental `LockFreeWaitlist` when many threads use one
+//! waitlist at once.
+//!
+//! Two workloads are run for each thread count:
+//!
+//! - mixed: every thread repeatedly registers a waiter, notifies one waiter, and finishes
+//!   its own wait, like `benches/contended.rs`.
+//! - split: half of the threads only register and wait until they are notified, and the
+//!   other half only notify, so registering and notifying contend with each other.
+//!
+//! The time reported is the mean time per completed wait.
+
+use std::hint::black_box;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::task::Context;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use futures_task::noop_waker;
+use waitlist::lockfree::LockFreeWaitlist;
+use waitlist::Waitlist;
+
+const ITERATIONS: usize = 100_000;
+
+macro_rules! workloads {
+    ($name:ident, $waitlist:ty) => {
+        mod $name {
+            use super::*;
+
+            pub fn mixed(threads: usize) -> Duration {
+                let waitlist = <$waitlist>::default();
+                let start = Instant::now();
+                thread::scope(|s| {
+                    for _ in 0..threads {
+                        s.spawn(|| {
+                            let waker = noop_waker();
+                            let cx = Context::from_waker(&waker);
+                            for _ in 0..ITERATIONS {
+                                let mut handle = waitlist.wait();
+                                handle.set_context(&cx);
+                                black_box(waitlist.notify_one());
+                                black_box(handle.finish());
+                            }
+                        });
+                    }
+                });
+                start.elapsed() / (threads * ITERATIONS) as u32
+            }
+
+            pub fn split(threads: usize) -> Duration {
+                let waitlist = <$waitlist>::default();
+                let waiters = (threads / 2).max(1);
+                let done = AtomicBool::new(false);
+                let start = Instant::now();
+                thread::scope(|s| {
+                    for _ in 0..(threads - waiters).max(1) {
+                        s.spawn(|| {
+                            while !done.load(Ordering::Relaxed) {
+                                if !waitlist.notify_one() {
+                                    thread::yield_now();
+                                }
+                            }
+                        });
+                    }
+                    let waiting: Vec<_> = (0..waiters)
+                        .map(|_| {
+                            s.spawn(|| {
+                                let waker = noop_waker();
+                                let cx = Context::from_waker(&waker);
+                                for _ in 0..ITERATIONS {
+                                    let mut handle = waitlist.wait();
+                                    handle.set_context(&cx);
+                                    while !handle.is_notified() {
+                                        thread::yield_now();
+                                    }
+                                    black_box(handle.finish());
+                                }
+                            })
+                        })
+                        .collect();
+                    for waiter in waiting {
+                        waiter.join().unwrap();
+                    }
+                    done.store(true, Ordering::Relaxed);
+                });
+                start.elapsed() / (waiters * ITERATIONS) as u32
+            }
+        }
+    };
+}
+
+workloads!(mutex, Waitlist);
+workloads!(lock_free, LockFreeWaitlist);
+
+fn main() {
+    println!(
+        "parallelism: {}",
+        thread::available_parallelism().map_or(1, |n| n.get())
+    );
+    for &threads in &[2, 4, 8] {
+        println!(
+            "{} threads: mixed {:>10?} mutex, {:>10?} lock-free; split {:>10?} mutex, {:>10?} lock-free",
+            threads,
+            mutex::mixed(threads),
+            lock_free::mixed(threads),
+            mutex::split(threads),
+            lock_free::split(threads),
+        );
+    }
+}
@@ -0,0 +1,80 @@
+//! Measure whether spinning on `try_notify_one` before blocking in `notify_one` helps the
+//! latency of notifying a contended waitlist.
+//!
+//! Background threads keep registering and finishing waiters, so the waitlist is nearly
+//! always waited on, and its lock often held. The main thread notifies it in a loop, first
+//! trying `try_notify_one` up to a number of times, with a spin hint in between, before
+//! falling back to `notify_one`, and records how long each notification takes. A spin
+//! count of 0 is plain `notify_one`.
+//!
+//! Spinning can only help while the thread holding the lock runs on another core, so the
+//! benchmark refuses to run on a single core, where the numbers would say nothing about it.
+
+use std::hint::spin_loop;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::task::Context;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use futures_task::noop_waker;
+use waitlist::Waitlist;
+
+const NOTIFICATIONS: usize = 200_000;
+const BACKGROUND_THREADS: usize = 3;
+
+fn notify(waitlist: &Waitlist, spins: usize) -> bool {
+    for _ in 0..spins {
+        if let Ok(woken) = waitlist.try_notify_one() {
+            return woken;
+        }
+        spin_loop();
+    }
+    waitlist.notify_one()
+}
+
+fn run(spins: usize) -> Vec<Duration> {
+    let waitlist = Waitlist::new();
+    let done = AtomicBool::new(false);
+    thread::scope(|s| {
+        for _ in 0..BACKGROUND_THREADS {
+            s.spawn(|| {
+                let waker = noop_waker();
+                let cx = Context::from_waker(&waker);
+                while !done.load(Ordering::Relaxed) {
+                    let mut handle = waitlist.wait();
+                    handle.set_context(&cx);
+                    handle.finish();
+                }
+            });
+        }
+        let mut latencies = Vec::with_capacity(NOTIFICATIONS);
+        for _ in 0..NOTIFICATIONS {
+            let start = Instant::now();
+            notify(&waitlist, spins);
+            latencies.push(start.elapsed());
+        }
+        done.store(true, Ordering::Relaxed);
+        latencies.sort();
+        latencies
+    })
+}
+
+fn main() {
+    let parallelism = thread::available_parallelism().map_or(1, |n| n.get());
+    if parallelism < 2 {
+        eprintln!("notify_spin needs at least 2 cores to measure contention, found 1");
+        return;
+    }
+    println!("parallelism: {}", parallelism);
+    for &spins in &[0, 4, 16, 64, 256] {
+        let latencies = run(spins);
+        let percentile = |p: usize| latencies[(latencies.len() - 1) * p / 100];
+        println!(
+            "{:>3} spins: p50 {:>10?}  p99 {:>10?}  p99.9 {:>10?}",
+            spins,
+            percentile(50),
+            percentile(99),
+            latencies[(latencies.len() - 1) * 999 / 1000],
+        );
+    }
+}
@@ -0,0 +1,46 @@
+//! Measure the cost of polling a parked task again with the same waker.
+//!
+//! Runtimes usually hand a task the same waker every time they poll it, so refreshing the
+//! waker of a task that is still waiting shouldn't need to clone it. As a comparison, the
+//! same loop is run alternating between wakers for two different tasks, which has to replace
+//! the stored waker, and bump the reference count of the new one, every time.
+
+use std::hint::black_box;
+use std::sync::Arc;
+use std::task::{Context, Wake, Waker};
+use std::time::{Duration, Instant};
+
+use waitlist::Waitlist;
+
+const ITERATIONS: u32 = 1_000_000;
+
+struct Task;
+
+impl Wake for Task {
+    fn wake(self: Arc<Self>) {}
+}
+
+fn run(wakers: &[Waker]) -> Duration {
+    let waitlist = Waitlist::new();
+    let mut handle = waitlist.wait();
+    handle.set_context(&Context::from_waker(&wakers[0]));
+    let start = Instant::now();
+    for i in 0..ITERATIONS as usize {
+        let waker = &wakers[i % wakers.len()];
+        handle.set_context(black_box(&Context::from_waker(waker)));
+    }
+    let elapsed = start.elapsed();
+    handle.finish();
+    elapsed / ITERATIONS
+}
+
+fn main() {
+    let first = Waker::from(Arc::new(Task));
+    let second = Waker::from(Arc::new(Task));
+    println!("mean time per set_context on a waiting task");
+    println!(
+        "         same waker {:>10?}",
+        run(std::slice::from_ref(&first))
+    );
+    println!("alternating wakers {:>10?}", run(&[first, second]));
+}
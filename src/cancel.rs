@@ -0,0 +1,82 @@
+//! Cancelling a wait from outside of the task that owns its [`WaitHandle`].
+//!
+//! A [`CancelToken`] is created from a handle with [`WaitHandle::cancel_token`], and can be
+//! cloned and sent to other threads. Calling [`CancelToken::cancel`] removes the handle's
+//! waiter from the waitlist, as long as it is still waiting, and wakes its task so that it
+//! can notice. The handle then behaves as if its waiter had been removed by
+//! [`WaitHandle::finish`]: `finish` and `cancel` return false and don't notify anyone else,
+//! and [`WaitHandle::try_finish`] returns true. [`WaitHandle::is_pending`] keeps returning
+//! true until one of those methods is called.
+
+use core::fmt;
+
+use crate::{WaitHandle, Waitlist};
+
+/// A token that cancels a single registered wait. See the [module level
+/// documentation](self).
+pub struct CancelToken<'a, T = ()> {
+    waitlist: &'a Waitlist<T>,
+    key: Option<usize>,
+}
+
+impl<T> CancelToken<'_, T> {
+    /// Remove the waiter this token was created for from the waitlist, and wake its task.
+    ///
+    /// This only does anything if the waiter is still waiting. If it was already notified,
+    /// finished or cancelled, or if the handle registered again with a new place in the
+    /// queue since the token was created, this does nothing. Returns true if the wait was
+    /// cancelled.
+    ///
+    /// Unlike [`WaitHandle::cancel`], this doesn't wake the next waiter. A waiter that is
+    /// still waiting has no notification to pass on, so waking the next one would only be
+    /// a spurious wakeup, and the cancelled task is woken instead, since nothing else would
+    /// wake it once its waiter is gone. A waiter that was already notified is left alone,
+    /// and its handle passes the notification on as usual if it is cancelled or dropped.
+    pub fn cancel(&self) -> bool {
+        let Some(key) = self.key else {
+            return false;
+        };
+        let mut inner = self.waitlist.lock();
+        if !inner.is_in_waiting_range(key) {
+            return false;
+        }
+        let Some(waiter) = inner.queue.remove(key) else {
+            return false;
+        };
+        inner.recompute_min_key();
+        inner.cancelled.insert(key);
+        inner.stats.total_cancelled += 1;
+        inner.to_wake.push(waiter.waker);
+        true
+    }
+}
+
+impl<T> Clone for CancelToken<'_, T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for CancelToken<'_, T> {}
+
+impl<T> fmt::Debug for CancelToken<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CancelToken")
+            .field("key", &self.key)
+            .finish()
+    }
+}
+
+impl<'a, T> WaitHandle<'a, T> {
+    /// Create a token that can cancel this handle's current wait from anywhere.
+    ///
+    /// The token is tied to the handle's current place in the queue, so it should be
+    /// created after [`WaitHandle::set_context`]. A token for a handle that isn't pending
+    /// never does anything.
+    pub fn cancel_token(&self) -> CancelToken<'a, T> {
+        CancelToken {
+            waitlist: self.waitlist,
+            key: self.key,
+        }
+    }
+}
@@ -0,0 +1,30 @@
+//! The source of time for the `timing` feature.
+//!
+//! Every timestamp a waitlist records, like when a waiter was parked or last refreshed, and
+//! every age computed from one, comes from its [`Clock`]. Waitlists use [`SystemClock`]
+//! unless they are created with [`Waitlist::with_clock`](crate::Waitlist::with_clock), so
+//! that tests can drive [`Waitlist::gc_stale`](crate::Waitlist::gc_stale) and the wait age
+//! methods with a clock they advance themselves, instead of sleeping.
+//!
+//! This module is only available with the `timing` feature.
+
+use std::time::Instant;
+
+/// A monotonic source of the current time.
+pub trait Clock: Send + Sync {
+    /// Return the current time.
+    ///
+    /// This must never return a time earlier than a previous call did.
+    fn now(&self) -> Instant;
+}
+
+/// The system's monotonic clock, [`Instant::now`].
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    #[inline]
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
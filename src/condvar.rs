@@ -0,0 +1,115 @@
+//! An async condition variable built on a [`Waitlist`].
+//!
+//! The usual way to wait for some shared state to change with a waitlist is a loop that
+//! registers the task, checks a condition, and goes back to sleep if the condition is still
+//! false. [`AsyncCondvar`] packages that loop up:
+//!
+//! ```
+//! # use std::sync::atomic::{AtomicBool, Ordering};
+//! # use waitlist::condvar::AsyncCondvar;
+//! # async fn example() {
+//! let condvar = AsyncCondvar::new();
+//! let ready = AtomicBool::new(false);
+//!
+//! // in one task
+//! condvar.wait_until(|| ready.load(Ordering::Relaxed)).await;
+//!
+//! // in another
+//! ready.store(true, Ordering::Relaxed);
+//! condvar.notify_all();
+//! # }
+//! ```
+//!
+//! The task is registered before the condition is checked, so a change made and notified
+//! while the condition is being checked isn't missed.
+
+use core::fmt;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+
+use crate::{WaitHandle, Waitlist};
+
+/// A condition variable for async tasks.
+///
+/// See the [module level documentation](self) for details.
+#[derive(Default)]
+pub struct AsyncCondvar {
+    waitlist: Waitlist,
+}
+
+/// Future returned by [`AsyncCondvar::wait_until`].
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct WaitUntil<'a, F> {
+    handle: WaitHandle<'a>,
+    pred: F,
+}
+
+impl AsyncCondvar {
+    /// Create a new `AsyncCondvar`
+    #[inline]
+    pub fn new() -> AsyncCondvar {
+        AsyncCondvar {
+            waitlist: Waitlist::new(),
+        }
+    }
+
+    /// Wait until `pred` returns true.
+    ///
+    /// `pred` is called when the future is first polled, and again each time the task is
+    /// notified. A notification that finds `pred` still false goes back to waiting, keeping
+    /// nothing from the notification. If the future is dropped after being woken by
+    /// [`AsyncCondvar::notify_one`], but before it could check `pred`, the notification is
+    /// passed on to the next waiting task.
+    pub fn wait_until<F: FnMut() -> bool>(&self, pred: F) -> WaitUntil<'_, F> {
+        WaitUntil {
+            handle: self.waitlist.wait(),
+            pred,
+        }
+    }
+
+    /// Wake the task that has waited longest, so that it checks its condition again.
+    ///
+    /// Returns true if a task was woken.
+    #[inline]
+    pub fn notify_one(&self) -> bool {
+        self.waitlist.notify_one()
+    }
+
+    /// Wake all waiting tasks, so that each checks its condition again.
+    ///
+    /// Returns true if any task was woken.
+    #[inline]
+    pub fn notify_all(&self) -> bool {
+        self.waitlist.notify_all()
+    }
+}
+
+impl fmt::Debug for AsyncCondvar {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("AsyncCondvar")
+            .field("waitlist", &self.waitlist)
+            .finish()
+    }
+}
+
+// The predicate is never pinned
+impl<F> Unpin for WaitUntil<'_, F> {}
+
+impl<F: FnMut() -> bool> Future for WaitUntil<'_, F> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let this = self.get_mut();
+        let pred = &mut this.pred;
+        this.handle.poll_until(cx, || pred().then_some(()))
+    }
+}
+
+impl<F> fmt::Debug for WaitUntil<'_, F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("WaitUntil")
+            .field("key", &self.handle.key())
+            .finish()
+    }
+}
@@ -0,0 +1,213 @@
+//! Coalescing repeated notifications for the same key into a single wake.
+//!
+//! The waitlist keeps an entry for each debounce key that at least one handle is registered
+//! for with [`WaitHandle::set_context_debounce`], and removes it once the last of those
+//! handles is dropped, reset or converted with [`WaitHandle::into_key`]. Each entry has the
+//! count of notifications not taken yet, and the keys of the waiters registered for it in
+//! the order they joined, so that notifying a debounce key doesn't search the whole queue.
+
+use alloc::borrow::Cow;
+use alloc::collections::VecDeque;
+use core::mem;
+use core::task::Context;
+
+use crate::{Inner, WaitHandle, Waitlist};
+
+/// The state of a debounce key.
+#[derive(Default)]
+pub(crate) struct Debounce {
+    /// Number of `notify_debounce` calls since the count was last taken
+    count: usize,
+    /// Number of handles registered for the key
+    handles: usize,
+    /// Keys of waiters registered for the key, oldest first. Waiters that have since left
+    /// the queue are only skipped when they reach the front, or dropped once they make up
+    /// most of the list.
+    waiting: VecDeque<usize>,
+}
+
+impl<T> Waitlist<T> {
+    /// Notify the task waiting with debounce key `key`, coalescing repeated notifications.
+    ///
+    /// Every call increments a counter for `key`. Only the call that takes the counter
+    /// from zero to one wakes a task: the oldest one waiting with
+    /// [`WaitHandle::set_context_debounce`] for `key`, or if there is none, the next one of
+    /// the handles registered for `key` to register again. Later calls only increment the
+    /// counter, until a woken task resets it with [`WaitHandle::take_coalesced_count`]. If
+    /// no handle is registered for `key` at all, the notification is dropped.
+    ///
+    /// Returns true if a task was woken.
+    pub fn notify_debounce(&self, key: u64) -> bool {
+        let mut inner = self.lock();
+        let Some(entry) = inner.debounce.get_mut(&key) else {
+            return false;
+        };
+        entry.count += 1;
+        entry.count == 1 && inner.notify_debounced(key)
+    }
+}
+
+impl<T> WaitHandle<'_, T> {
+    /// Like [`WaitHandle::set_context`], but also register the task for notifications
+    /// from [`Waitlist::notify_debounce`] with the same `key`.
+    ///
+    /// The task can still be notified by the other `notify_*` methods. If `key` already
+    /// has notifications that haven't been taken, the task is woken immediately. The
+    /// handle stays registered for `key` until it is dropped, reset, or registered for a
+    /// different key.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the waitlist is at the waiter limit set by [`Waitlist::with_fixed_capacity`]
+    /// or [`Waitlist::with_max_waiters`].
+    pub fn set_context_debounce(&mut self, cx: &Context<'_>, key: u64) {
+        let mut inner = self.waitlist.lock();
+        if self.reject_closed(&mut inner, cx.waker()) {
+            return;
+        }
+        self.register(&mut inner, Cow::Borrowed(cx.waker()))
+            .expect("waitlist is full");
+        if self.debounce != Some(key) {
+            self.release_debounce(&mut inner);
+            inner.debounce.entry(key).or_default().handles += 1;
+            self.debounce = Some(key);
+        }
+        let wkey = self.key.unwrap();
+        let joined = match inner.queue.get_mut(wkey) {
+            Some(w) => w.debounce.replace(key) != Some(key),
+            None => false,
+        };
+        if joined {
+            inner.debounce_joined(key, wkey);
+        }
+        if inner
+            .debounce
+            .get(&key)
+            .is_some_and(|entry| entry.count > 0)
+        {
+            inner.notify_debounced(key);
+        }
+    }
+
+    /// Return the number of [`Waitlist::notify_debounce`] calls for this handle's debounce
+    /// key since the count was last taken, and reset the count to zero.
+    ///
+    /// The count is not reset by the wake itself, so notifications that arrive after the
+    /// task was woken but before it calls this are included in the count rather than
+    /// waking it again. Once the count is taken, the next notification for the key wakes a
+    /// task again.
+    ///
+    /// Returns 0 if the handle was never registered with
+    /// [`WaitHandle::set_context_debounce`]. The key isn't kept by
+    /// [`WaitHandle::into_key`] and [`WaitHandle::from_key`].
+    pub fn take_coalesced_count(&mut self) -> usize {
+        match self.debounce {
+            Some(key) => self
+                .waitlist
+                .lock()
+                .debounce
+                .get_mut(&key)
+                .map_or(0, |entry| mem::take(&mut entry.count)),
+            None => 0,
+        }
+    }
+
+    /// Stop counting this handle as registered for its debounce key, if it has one.
+    pub(crate) fn release_debounce(&mut self, inner: &mut Inner<T>) {
+        let Some(key) = self.debounce.take() else {
+            return;
+        };
+        if let Some(entry) = inner.debounce.get_mut(&key) {
+            entry.handles -= 1;
+            if entry.handles == 0 {
+                inner.debounce.remove(&key);
+            }
+        }
+    }
+}
+
+impl<T> Inner<T> {
+    /// Record that the waiter for `wkey` is now waiting for debounce key `key`.
+    fn debounce_joined(&mut self, key: u64, wkey: usize) {
+        let Inner {
+            debounce, queue, ..
+        } = self;
+        let Some(entry) = debounce.get_mut(&key) else {
+            return;
+        };
+        // each handle has at most one waiter in the queue, so once most of the list is
+        // waiters that left, drop them
+        if entry.waiting.len() >= 2 * entry.handles {
+            entry
+                .waiting
+                .retain(|&k| queue.get_mut(k).is_some_and(|w| w.debounce == Some(key)));
+        }
+        entry.waiting.push_back(wkey);
+    }
+
+    /// Wake the oldest waiter registered with debounce key `key`, if any.
+    fn notify_debounced(&mut self, key: u64) -> bool {
+        loop {
+            let Some(wkey) = self
+                .debounce
+                .get_mut(&key)
+                .and_then(|entry| entry.waiting.pop_front())
+            else {
+                return false;
+            };
+            let registered = self.is_in_waiting_range(wkey)
+                && self
+                    .queue
+                    .get_mut(wkey)
+                    .is_some_and(|w| w.debounce == Some(key));
+            if registered {
+                return self.notify_key(wkey);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures_task::noop_waker;
+
+    #[test]
+    fn debounce_map_stays_bounded() {
+        let waitlist = Waitlist::new();
+        let waker = noop_waker();
+        let cx = Context::from_waker(&waker);
+
+        // nobody is registered for these keys, so nothing is kept for them
+        for key in 0..1000 {
+            assert!(!waitlist.notify_debounce(key));
+        }
+        assert_eq!(0, waitlist.lock().debounce.len());
+
+        // waiters that left the queue another way don't pile up for a key
+        let mut handle = waitlist.wait();
+        for _ in 0..1000 {
+            handle.set_context_debounce(&cx, 1);
+            assert!(waitlist.notify_one());
+        }
+        {
+            let inner = waitlist.lock();
+            assert_eq!(1, inner.debounce.len());
+            assert!(inner.debounce[&1].waiting.len() <= 2);
+        }
+        handle.set_context_debounce(&cx, 1);
+        assert!(waitlist.notify_debounce(1));
+        assert!(!waitlist.notify_debounce(1));
+        assert_eq!(2, handle.take_coalesced_count());
+
+        // the entry goes away with the last handle registered for it
+        let mut other = waitlist.wait();
+        other.set_context_debounce(&cx, 1);
+        drop(handle);
+        assert_eq!(1, waitlist.lock().debounce.len());
+        other.set_context_debounce(&cx, 2);
+        assert_eq!(1, waitlist.lock().debounce.len());
+        other.reset();
+        assert_eq!(0, waitlist.lock().debounce.len());
+    }
+}
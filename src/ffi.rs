@@ -0,0 +1,197 @@
+//! A C interface to [`Waitlist`], using callbacks as wakers.
+//!
+//! # Ownership
+//!
+//! * A waitlist is created with [`waitlist_new`] and must be freed exactly once with
+//!   [`waitlist_free`]. It must not be freed while another thread is using it, or while
+//!   any [`WaitlistKey`] for it is still pending.
+//! * A [`WaitlistKey`] is a plain value owned by the C code, standing in for a
+//!   [`WaitHandle`]. Initialize it with [`waitlist_key_init`] (or zero it), keep using the
+//!   same key for the same wait, and end the wait with [`waitlist_finish`] or
+//!   [`waitlist_cancel`]. A key that is simply forgotten while pending leaks its place in
+//!   the queue, like [`WaitHandle::into_key`]. A key must not be copied and used twice.
+//! * The `context` pointer passed to [`waitlist_register`] is owned by the waitlist from
+//!   then on. It is passed to `wake` each time the task is woken, and to `release` (if
+//!   not null) once the waitlist no longer needs it, after which it isn't used again.
+//!   Registering again replaces the previous callback, which is then released.
+//!
+//! `wake` and `release` may be called from any thread that uses the waitlist, including
+//! concurrently, and must not call back into the same waitlist.
+
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::task::Wake;
+use core::ffi::c_void;
+use core::task::{Context, Waker};
+
+use crate::{Key, WaitHandle, Waitlist};
+
+/// The state of a single wait, standing in for a [`WaitHandle`]. See [`waitlist_key_init`].
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct WaitlistKey {
+    /// Whether `key` refers to a waiter on the waitlist
+    pub pending: bool,
+    /// Only meaningful if `pending` is true
+    pub key: usize,
+}
+
+/// A C callback used as a waker.
+struct Callback {
+    wake: extern "C" fn(*mut c_void),
+    release: Option<extern "C" fn(*mut c_void)>,
+    context: *mut c_void,
+}
+
+// The caller of `waitlist_register` guarantees that the callbacks and context can be used
+// from any thread.
+unsafe impl Send for Callback {}
+unsafe impl Sync for Callback {}
+
+impl Wake for Callback {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref()
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        (self.wake)(self.context)
+    }
+}
+
+impl Drop for Callback {
+    fn drop(&mut self) {
+        if let Some(release) = self.release {
+            release(self.context)
+        }
+    }
+}
+
+impl WaitlistKey {
+    fn get(&self) -> Option<Key> {
+        if self.pending {
+            Some(Key::from_raw(self.key))
+        } else {
+            None
+        }
+    }
+
+    fn set(&mut self, key: Option<Key>) {
+        self.pending = key.is_some();
+        self.key = key.map_or(0, Key::into_raw);
+    }
+}
+
+/// Run `f` on the handle for `key`, and store the handle's key back into it.
+unsafe fn with_handle<R>(
+    waitlist: *const Waitlist,
+    key: *mut WaitlistKey,
+    f: impl FnOnce(&mut WaitHandle<'_>) -> R,
+) -> R {
+    let key = &mut *key;
+    let mut handle = WaitHandle::from_key(&*waitlist, key.get());
+    let result = f(&mut handle);
+    key.set(handle.into_key());
+    result
+}
+
+/// Create a new, empty waitlist. Free it with [`waitlist_free`].
+#[no_mangle]
+pub extern "C" fn waitlist_new() -> *mut Waitlist {
+    Box::into_raw(Box::new(Waitlist::new()))
+}
+
+/// Free a waitlist created with [`waitlist_new`].
+///
+/// # Safety
+///
+/// `waitlist` must have been returned by `waitlist_new` and not freed yet, and must not be
+/// used again after this call. Passing null does nothing.
+#[no_mangle]
+pub unsafe extern "C" fn waitlist_free(waitlist: *mut Waitlist) {
+    if !waitlist.is_null() {
+        drop(Box::from_raw(waitlist));
+    }
+}
+
+/// Initialize `key` as not pending.
+///
+/// # Safety
+///
+/// `key` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn waitlist_key_init(key: *mut WaitlistKey) {
+    key.write(WaitlistKey {
+        pending: false,
+        key: 0,
+    });
+}
+
+/// Register `wake(context)` to be called when the wait for `key` is notified. This is the
+/// equivalent of [`WaitHandle::set_context`].
+///
+/// `release(context)` is called once the waitlist no longer needs `context`, if `release`
+/// isn't null. See the [module level documentation](self) for the ownership rules.
+///
+/// # Safety
+///
+/// `waitlist` must be a live waitlist and `key` a valid key for it. `wake` and `release` must
+/// be safe to call with `context` from any thread until `release` is called.
+#[no_mangle]
+pub unsafe extern "C" fn waitlist_register(
+    waitlist: *const Waitlist,
+    key: *mut WaitlistKey,
+    wake: extern "C" fn(*mut c_void),
+    release: Option<extern "C" fn(*mut c_void)>,
+    context: *mut c_void,
+) {
+    let waker = Waker::from(Arc::new(Callback {
+        wake,
+        release,
+        context,
+    }));
+    with_handle(waitlist, key, |handle| {
+        handle.set_context(&Context::from_waker(&waker))
+    });
+}
+
+/// End the wait for `key`, returning true if it had been notified. This is the equivalent
+/// of [`WaitHandle::finish`].
+///
+/// # Safety
+///
+/// `waitlist` must be a live waitlist and `key` a valid key for it.
+#[no_mangle]
+pub unsafe extern "C" fn waitlist_finish(waitlist: *const Waitlist, key: *mut WaitlistKey) -> bool {
+    with_handle(waitlist, key, |handle| handle.finish())
+}
+
+/// Cancel the wait for `key`, passing on its notification if it had one. This is the
+/// equivalent of [`WaitHandle::cancel`].
+///
+/// # Safety
+///
+/// `waitlist` must be a live waitlist and `key` a valid key for it.
+#[no_mangle]
+pub unsafe extern "C" fn waitlist_cancel(waitlist: *const Waitlist, key: *mut WaitlistKey) -> bool {
+    with_handle(waitlist, key, |handle| handle.cancel())
+}
+
+/// See [`Waitlist::notify_one`].
+///
+/// # Safety
+///
+/// `waitlist` must be a live waitlist.
+#[no_mangle]
+pub unsafe extern "C" fn waitlist_notify_one(waitlist: *const Waitlist) -> bool {
+    (*waitlist).notify_one()
+}
+
+/// See [`Waitlist::notify_all`].
+///
+/// # Safety
+///
+/// `waitlist` must be a live waitlist.
+#[no_mangle]
+pub unsafe extern "C" fn waitlist_notify_all(waitlist: *const Waitlist) -> bool {
+    (*waitlist).notify_all()
+}
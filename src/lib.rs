@@ -1,21 +1,136 @@
-use std::collections::vec_deque::VecDeque;
-use std::fmt;
-use std::mem;
-use std::ops::{Deref, DerefMut};
-use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::{Mutex, MutexGuard};
-use std::task::{Context, Waker};
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(not(any(feature = "std", feature = "no_std")))]
+compile_error!("either the `std` or the `no_std` feature must be enabled");
+
+extern crate alloc;
+#[cfg(all(test, not(feature = "std")))]
+extern crate std;
+
+use alloc::borrow::Cow;
+use alloc::sync::Arc;
+use alloc::task::Wake;
+use alloc::vec::Vec;
+#[cfg(feature = "timing")]
+use clock::{Clock, SystemClock};
+use core::error::Error;
+use core::fmt;
+use core::future::Future;
+use core::mem;
+use core::ops::{Deref, DerefMut};
+use core::pin::Pin;
+use core::sync::atomic::Ordering;
+use core::task::{Context, Poll, Waker};
+#[cfg(feature = "timing")]
+use std::time::{Duration, Instant};
+
+#[cfg(not(feature = "std"))]
+use alloc::collections::{BTreeMap as Map, BTreeSet as Set};
+#[cfg(feature = "std")]
+use std::collections::{HashMap as Map, HashSet as Set};
+
+#[cfg(feature = "channel")]
+use futures_channel::mpsc::UnboundedSender;
+#[cfg(feature = "stream")]
+use futures_core::Stream;
+
+pub mod cancel;
+#[cfg(feature = "timing")]
+pub mod clock;
+pub mod condvar;
+mod debounce;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "lockfree")]
+pub mod lockfree;
+pub mod notify;
+pub mod parkable;
+pub mod priority;
+mod queue;
+pub mod select;
+pub mod semaphore;
+pub mod shared;
+mod sync;
+
+use queue::WaiterQueue;
+use sync::{fence, AtomicUsize, Mutex, MutexGuard};
 
 struct Waiter {
     key: usize,
     waker: Waker,
+    /// The key passed to `WaitHandle::set_context_debounce`, if any
+    debounce: Option<u64>,
+    /// The token passed to `WaitHandle::set_context_with_token`, or 0
+    token: u64,
+    /// When the waiter was added to the queue
+    #[cfg(feature = "timing")]
+    parked: Instant,
+    /// When the waker was last set
+    #[cfg(feature = "timing")]
+    refreshed: Instant,
 }
 
-struct Inner {
-    queue: VecDeque<Waiter>,
+/// The shared state of a waitlist.
+///
+/// Keys are handed out sequentially from `next_key`, wrapping around at `usize::MAX`. The
+/// keys of the waiters still in the queue are always in the range `min_key..next_key`
+/// (which may itself wrap around), and any key outside of it belongs to a task that was
+/// already notified. `notified_count` is bounded by the number of keys, since every
+/// notified task took a key from the queue.
+///
+/// This stays unambiguous as long as no key is still in use by the time `next_key` wraps
+/// all the way around to it. Keys aren't checked for collisions when they are handed out,
+/// since that would need a lookup for every insertion, so a task that stays parked, or
+/// keeps a notification without handling it, while `usize::MAX` other waiters register
+/// would have its key reused, and the two handles would be mixed up. For a parked task,
+/// that is caught with a debug assertion, when the range of waiting keys becomes empty.
+/// On 64-bit targets it would take centuries of constant registrations. On 32-bit ones
+/// it needs about four billion registrations while the task waits, and a waitlist that
+/// busy should have its waiters notified long before then.
+///
+/// The queue is normally sorted by key, so `min_key` can be taken from the front waiter.
+/// `WaitHandle::set_context_front` breaks that by putting a new key in front of older ones.
+/// While the queue is `unordered`, `min_key` is only kept below every queued key, and
+/// isn't advanced. That is enough because the range is only a quick check before looking
+/// a key up in the queue's index, so a range that also covers some keys that aren't
+/// waiting costs a lookup, but doesn't change any result.
+struct Inner<T> {
+    queue: WaiterQueue,
+    /// Values sent with `notify_one_with`, by the key of the notified waiter.
+    ///
+    /// Waiters leave the queue as soon as they are notified, so the payload is kept here
+    /// until the task takes it or the notification is handled.
+    payloads: Map<usize, T>,
     notified_count: usize,
     min_key: usize,
     next_key: usize,
+    /// Set when `WaitHandle::set_context_front` put a waiter in front of waiters with older
+    /// keys, and cleared once the queue is empty again
+    unordered: bool,
+    limit: Option<usize>,
+    /// Which end of the queue notifications are taken from
+    mode: WaitlistMode,
+    /// Wakers of tasks waiting for `notified_count` to drop to zero
+    drain_wakers: Vec<Waker>,
+    adaptive: AdaptivePolicy,
+    /// Recent demand seen by `notify_adaptive`
+    pressure: usize,
+    /// The state of each debounce key that a handle is registered for
+    debounce: Map<u64, debounce::Debounce>,
+    /// Keys of waiters removed by a `CancelToken` or `Waitlist::clear`, that their handle
+    /// hasn't seen yet
+    cancelled: Set<usize>,
+    /// Set by `Waitlist::shutdown`
+    closed: bool,
+    /// Counts accumulated while locked, added to `Waitlist::stats` when unlocked
+    stats: WaitlistStats,
+    /// Wakers of notified tasks, woken once the waitlist is unlocked
+    to_wake: Wakers,
+    /// Where the timestamps of waiters come from
+    #[cfg(feature = "timing")]
+    clock: Box<dyn Clock>,
+    #[cfg(feature = "channel")]
+    subscribers: Vec<UnboundedSender<()>>,
 }
 
 // Set when there is at least one notifiable waker
@@ -25,18 +140,182 @@ const WAITING: usize = 1 << 1;
 // yet been removed
 const NOTIFIED: usize = 1 << 2;
 
+// Set when there is at least one channel subscribed to notifications
+const SUBSCRIBED: usize = 1 << 3;
+
 /// An ordered list of [`std::task::Waker`]s.
 ///
-/// This allows waking wakers in the same order that they were added to this queue.
-pub struct Waitlist {
+/// This allows waking wakers in the same order that they were added to this queue, or in
+/// the reverse order for a waitlist created with [`Waitlist::new_lifo`].
+///
+/// `T` is the type of the values that can be handed to woken tasks with
+/// [`Waitlist::notify_one_with`]. It defaults to `()`, which is what [`Waitlist::new`] and
+/// the other constructors create. Use `Waitlist::<T>::default()` to create a waitlist with a
+/// different payload type.
+///
+/// # Memory ordering
+///
+/// All state is protected by a mutex, so every operation that takes the lock is ordered
+/// with every other one. The `notify_*` methods first check an atomic summary of the state
+/// without locking, and return early if it says there is nothing to do. That check
+/// guarantees that a notification wakes every task whose registration (for example
+/// [`WaitHandle::set_context`]) *happens before* the call to `notify_*`, however that
+/// relation was established. A registration that is concurrent with the notification,
+/// with nothing ordering the two, may or may not be woken, exactly as if both had taken
+/// the lock in either order.
+///
+/// The summary is written and read behind sequentially consistent fences, so the usual
+/// pattern is also safe with any ordering for the condition: a task registers and then
+/// checks its condition, and the notifier sets the condition before calling `notify_*`.
+/// Either the task sees the condition, or the notification wakes it.
+///
+/// The early return doesn't synchronize with anything. Code that needs a notification to
+/// publish other data to the woken task should publish it with its own synchronization,
+/// or rely on the task taking the waitlist's lock when it is polled.
+pub struct Waitlist<T = ()> {
     flags: AtomicUsize,
-    inner: Mutex<Inner>,
+    inner: Mutex<Inner<T>>,
+    /// Waitlists linked with `link_child`. This is never locked at the same time as
+    /// `inner`.
+    children: Mutex<Vec<Arc<Waitlist<T>>>>,
+    stats: StatCounters,
+    /// Identifies this waitlist to a `SelectToken` registered on it
+    select_id: usize,
+}
+
+#[derive(Default)]
+struct StatCounters {
+    woken: AtomicUsize,
+    inserted: AtomicUsize,
+    cancelled: AtomicUsize,
+    /// Like `woken`, but never reset
+    woken_total: AtomicUsize,
+    /// Like `inserted`, but never reset
+    inserted_total: AtomicUsize,
+}
+
+/// Counters returned by [`Waitlist::take_stats`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WaitlistStats {
+    /// The number of waiters notified
+    pub total_woken: usize,
+    /// The number of waiters added to the queue
+    pub total_inserted: usize,
+    /// The number of calls to [`WaitHandle::cancel`], including from dropping a handle,
+    /// and of waits cancelled with a [`CancelToken`](cancel::CancelToken)
+    pub total_cancelled: usize,
+}
+
+/// Counts returned by [`Waitlist::stats`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WaitlistTotals {
+    /// The number of waiters added to the queue since the waitlist was created
+    pub enqueued_total: usize,
+    /// The number of waiters notified since the waitlist was created
+    pub woken_total: usize,
+    /// The number of tasks waiting to be notified, as returned by [`Waitlist::len`]
+    pub currently_waiting: usize,
+}
+
+/// The waker returned by `Waitlist::forwarding_waker`
+struct Forward<T>(Arc<Waitlist<T>>);
+
+impl<T: Send + 'static> Wake for Forward<T> {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref()
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.0.notify_one();
+    }
+}
+
+/// Held while linking waitlists, so that concurrent links can't form a cycle
+#[cfg(not(loom))]
+static LINK_LOCK: Mutex<()> = Mutex::new(());
+
+#[cfg(loom)]
+loom::lazy_static! {
+    static ref LINK_LOCK: Mutex<()> = Mutex::new(());
+}
+
+/// Parameters controlling how many waiters [`Waitlist::notify_adaptive`] wakes.
+///
+/// Each call to `notify_adaptive` that leaves waiters in the queue adds one to a pressure
+/// counter, and each call that empties the queue halves it. A call wakes one waiter, plus
+/// one more for every `threshold` of pressure, up to `max_batch` waiters in total.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AdaptivePolicy {
+    /// The most waiters a single call may wake.
+    ///
+    /// The default is 1, which makes `notify_adaptive` equivalent to `notify_one`.
+    pub max_batch: usize,
+    /// The amount of pressure needed for each additional waiter woken.
+    ///
+    /// Defaults to 8. A value of 0 is treated as 1.
+    pub threshold: usize,
+}
+
+impl Default for AdaptivePolicy {
+    fn default() -> Self {
+        AdaptivePolicy {
+            max_batch: 1,
+            threshold: 8,
+        }
+    }
+}
+
+/// Identifies a waiting task's place in a [`Waitlist`].
+///
+/// Keys are returned by [`WaitHandle::into_key`] and the `notify_*_key` methods, and are
+/// only meaningful for the waitlist that handed them out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Key(usize);
+
+impl Key {
+    /// Rebuild a key from a value returned by [`Key::into_raw`].
+    pub fn from_raw(raw: usize) -> Key {
+        Key(raw)
+    }
+
+    /// Return the key as a plain integer, for storing it compactly.
+    pub fn into_raw(self) -> usize {
+        self.0
+    }
 }
 
 /// Handle for controlling the wait status of a task.
-pub struct WaitHandle<'a> {
-    waitlist: &'a Waitlist,
+pub struct WaitHandle<'a, T = ()> {
+    waitlist: &'a Waitlist<T>,
     key: Option<usize>,
+    debounce: Option<u64>,
+    /// A payload taken from the waitlist when the handle learned it was notified
+    payload: Option<T>,
+}
+
+/// The order in which a [`Waitlist`] wakes its waiting tasks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WaitlistMode {
+    /// Wake the task that has been waiting longest first.
+    #[default]
+    Fifo,
+    /// Wake the task that started waiting most recently first.
+    ///
+    /// This can improve cache locality when the most recent waiter is likely to still have
+    /// its data in cache, at the cost of fairness: under constant load, the oldest waiters
+    /// may never be woken.
+    Lifo,
+    /// Like `Fifo`, but a task that registers again after being notified gets its original
+    /// place in the queue back.
+    ///
+    /// In `Fifo` mode, a task that is notified, finds that it can't make progress yet, and
+    /// waits again, queues behind every task that started waiting while it was being
+    /// polled. In this mode it goes back in front of those tasks, behind only the tasks
+    /// that started waiting before it did, so tasks are woken in the order they first
+    /// started waiting even if they are notified spuriously, for example by
+    /// [`Waitlist::notify_all`]. A task only keeps its place while its handle does: a new
+    /// handle, or one that was finished or cancelled, always goes to the back.
+    FairRequeue,
 }
 
 impl Waitlist {
@@ -48,28 +327,137 @@ impl Waitlist {
 
     /// Create a new waitlist with a given initial capacity
     ///
-    /// This determines how much capacity the underlying `Vec` should be created with.
+    /// This determines how many tasks can wait at once before the waitlist has to allocate
+    /// more memory.
+    ///
+    /// # Allocation
+    ///
+    /// The storage for a waiter is freed for reuse as soon as it is notified or cancelled,
+    /// so memory is only allocated when more tasks are waiting at once than ever before.
+    /// When that happens, registering the task with [`WaitHandle::set_context`] grows the
+    /// storage, to about twice its size, while the waitlist is locked, which delays every other
+    /// operation on the waitlist until it is done. Storage is never shrunk automatically,
+    /// only by [`Waitlist::shrink_to_fit`]. To avoid growing on a latency sensitive path,
+    /// size the waitlist up front, or call [`Waitlist::reserve`] at a convenient time.
     #[inline]
     pub fn with_capacity(cap: usize) -> Waitlist {
+        Self::with_limit(cap, None, WaitlistMode::Fifo)
+    }
+
+    /// Create a new waitlist with room for about `n` tasks to wait at once, and some slack.
+    ///
+    /// The number of waiting tasks often fluctuates around a steady value, so this
+    /// reserves a quarter more than `n`, so that the occasional peak above `n` doesn't
+    /// grow the storage while the waitlist is locked. See [`Waitlist::with_capacity`] for
+    /// when memory is allocated.
+    #[inline]
+    pub fn with_expected_waiters(n: usize) -> Waitlist {
+        Self::with_capacity(n.saturating_add(n.div_ceil(4)))
+    }
+
+    /// Create a new waitlist that wakes the most recently added task first.
+    ///
+    /// This is the same as `Waitlist::with_capacity_and_mode(0, WaitlistMode::Lifo)`.
+    #[inline]
+    pub fn new_lifo() -> Waitlist {
+        Self::with_capacity_and_mode(0, WaitlistMode::Lifo)
+    }
+
+    /// Create a new waitlist with a given initial capacity, that wakes tasks in the order
+    /// given by `mode`.
+    #[inline]
+    pub fn with_capacity_and_mode(cap: usize, mode: WaitlistMode) -> Waitlist {
+        Self::with_limit(cap, None, mode)
+    }
+
+    /// Create a new waitlist that can never hold more than `cap` waiting tasks.
+    ///
+    /// Storage for `cap` waiters is allocated up front, and registering a task when
+    /// `cap` tasks are already waiting fails instead of growing the queue. Use
+    /// [`WaitHandle::try_set_context`] to observe that failure.
+    #[inline]
+    pub fn with_fixed_capacity(cap: usize) -> Waitlist {
+        Self::with_limit(cap, Some(cap), WaitlistMode::Fifo)
+    }
+
+    /// Create a new waitlist that rejects new tasks once `max` tasks are waiting, but
+    /// allocates storage as it is needed.
+    ///
+    /// This behaves like [`Waitlist::with_fixed_capacity`], so registering a task past the
+    /// limit fails with [`WaitlistFull`] from [`WaitHandle::try_set_context`], but suits a
+    /// large limit, such as a budget for how many tasks may wait on a channel, that is
+    /// rarely reached. Only the storage for tasks that are actually waiting is allocated.
+    #[inline]
+    pub fn with_max_waiters(max: usize) -> Waitlist {
+        Self::with_limit(0, Some(max), WaitlistMode::Fifo)
+    }
+
+    /// Create a new `Waitlist` that takes its timestamps from `clock` instead of
+    /// [`SystemClock`].
+    ///
+    /// This is meant for testing code that uses [`Waitlist::gc_stale`] or
+    /// [`Waitlist::oldest_wait_age`] with a clock the test advances itself.
+    #[cfg(feature = "timing")]
+    pub fn with_clock<C: Clock + 'static>(clock: C) -> Waitlist {
+        let waitlist = Waitlist::new();
+        waitlist.lock().clock = Box::new(clock);
+        waitlist
+    }
+}
+
+impl<T> Waitlist<T> {
+    fn with_limit(cap: usize, limit: Option<usize>, mode: WaitlistMode) -> Waitlist<T> {
         Waitlist {
             flags: AtomicUsize::new(0),
-            inner: Mutex::new(Inner {
-                queue: VecDeque::with_capacity(cap),
-                notified_count: 0,
-                min_key: 0,
-                next_key: 0,
-            }),
+            inner: Mutex::new(Inner::new(cap, limit, mode)),
+            children: Mutex::new(Vec::new()),
+            stats: StatCounters::default(),
+            select_id: select::new_id(),
         }
     }
 
+    /// Read the summary flags without locking.
+    ///
+    /// The fence pairs with the one after the flags are stored when a `Guard` is dropped.
+    /// The load itself can be relaxed: SeqCst fences are totally ordered, so either the
+    /// registering task's fence comes first, and this load sees the flags it stored (or
+    /// later ones), or this fence comes first, and everything the notifier wrote before
+    /// calling `notify_*`, like the task's condition, is visible to the task once it
+    /// checks it after registering. Acquire and release accesses alone wouldn't be
+    /// enough, since neither side reads a value written by the other in that case.
+    #[inline]
+    fn flags(&self) -> usize {
+        fence(Ordering::SeqCst);
+        self.flags.load(Ordering::Relaxed)
+    }
+
     /// Lock `inner`, and give a new guard that includes the atomic flags
-    fn lock(&self) -> Guard<'_> {
+    ///
+    /// If the mutex was poisoned, the poison is ignored. Wakers are only woken once the
+    /// guard has unlocked the mutex, so a waker can use the waitlist again, and a
+    /// panicking waker can't leave it in an inconsistent state.
+    fn lock(&self) -> Guard<'_, T> {
+        #[cfg(test)]
+        test::LOCK_COUNT.with(|c| c.set(c.get() + 1));
         Guard {
             flags: &self.flags,
-            inner: self.inner.lock().unwrap(),
+            counters: &self.stats,
+            inner: self.inner.lock(),
+            to_wake: Wakers::default(),
         }
     }
 
+    fn try_lock(&self) -> Option<Guard<'_, T>> {
+        #[cfg(test)]
+        test::LOCK_COUNT.with(|c| c.set(c.get() + 1));
+        Some(Guard {
+            flags: &self.flags,
+            counters: &self.stats,
+            inner: self.inner.try_lock()?,
+            to_wake: Wakers::default(),
+        })
+    }
+
     /// Return a handle a task can use to wait for events
     ///
     /// Calling this method doesn't do anything itself, but gives you an object
@@ -77,10 +465,227 @@ impl Waitlist {
     /// that it is notified when one of the `notify_*` methods is called. It is also used to mark
     /// the the task as done or canceled.
     #[inline]
-    pub fn wait(&self) -> WaitHandle<'_> {
+    pub fn wait(&self) -> WaitHandle<'_, T> {
         WaitHandle {
             waitlist: self,
             key: None,
+            debounce: None,
+            payload: None,
+        }
+    }
+
+    /// Register a task for each context in `cxs`, taking the lock only once, and return
+    /// their handles in the same order.
+    ///
+    /// This is the same as calling [`Waitlist::wait`] and then [`WaitHandle::set_context`]
+    /// for each context in turn, so the tasks are queued in order, with consecutive keys.
+    /// If the waitlist was shut down, every context is woken and none of the handles are
+    /// pending.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the waiter limit set by [`Waitlist::with_fixed_capacity`] or
+    /// [`Waitlist::with_max_waiters`] doesn't leave room for all of the tasks. In that case
+    /// none of them are registered.
+    pub fn wait_many<'c, 'w>(
+        &self,
+        cxs: impl IntoIterator<Item = &'c Context<'w>>,
+    ) -> Vec<WaitHandle<'_, T>>
+    where
+        'w: 'c,
+    {
+        let cxs: Vec<&Context<'_>> = cxs.into_iter().collect();
+        let mut handles: Vec<_> = cxs.iter().map(|_| self.wait()).collect();
+        let mut inner = self.lock();
+        if inner.closed {
+            drop(inner);
+            for cx in cxs {
+                cx.waker().wake_by_ref();
+            }
+            return handles;
+        }
+        let room = inner
+            .limit
+            .is_none_or(|limit| limit.saturating_sub(inner.queue.len()) >= cxs.len());
+        if !room {
+            // the handles aren't pending, so they can be dropped while locked
+            drop(handles);
+            drop(inner);
+            panic!("waitlist is full");
+        }
+        for (handle, cx) in handles.iter_mut().zip(cxs) {
+            handle.key = Some(inner.insert(cx));
+        }
+        drop(inner);
+        handles
+    }
+
+    /// Return the number of tasks waiting to be notified.
+    ///
+    /// This only counts tasks that are still in the queue. Tasks that were notified but
+    /// haven't called [`WaitHandle::finish`] or registered again yet aren't included.
+    pub fn len(&self) -> usize {
+        if self.flags() & WAITING == 0 {
+            return 0;
+        }
+        self.lock().queue.len()
+    }
+
+    /// Return the number of tasks that were notified and haven't handled it yet.
+    ///
+    /// A notified task handles its notification by calling [`WaitHandle::finish`] or
+    /// [`WaitHandle::cancel`], by registering again, or by dropping its handle. This is
+    /// not the number of notifications ever sent, see [`Waitlist::take_stats`] for that.
+    /// Together with [`Waitlist::len`] it gives every task with a wait in progress, and a
+    /// value that keeps growing usually means that notified tasks are never finished.
+    pub fn notified_count(&self) -> usize {
+        if self.flags() & NOTIFIED == 0 {
+            return 0;
+        }
+        self.lock().notified_count
+    }
+
+    /// Return the key of the task that would be woken next, without waking it.
+    ///
+    /// Other threads may notify, add or remove waiters at any time, so the result is only
+    /// accurate until the waitlist is next changed. Returns `None` if no task is waiting.
+    pub fn peek(&self) -> Option<Key> {
+        if self.flags() & WAITING == 0 {
+            return None;
+        }
+        self.lock().wake_order().next().map(|w| Key(w.key))
+    }
+
+    /// Return true if the task for `key` is still waiting to be notified.
+    ///
+    /// This is false once the task was notified, or its wait was finished or cancelled.
+    /// Like [`Waitlist::len`], the answer may be out of date by the time it is returned if
+    /// other threads use the waitlist.
+    pub fn contains(&self, key: Key) -> bool {
+        if self.flags() & WAITING == 0 {
+            return false;
+        }
+        self.lock().is_waiting(key.0)
+    }
+
+    /// Return the keys of all waiting tasks, in the order they would be woken.
+    ///
+    /// This is a snapshot taken under the lock, and may be out of date by the time it is
+    /// returned if other threads use the waitlist.
+    pub fn keys(&self) -> Vec<Key> {
+        if self.flags() & WAITING == 0 {
+            return Vec::new();
+        }
+        let inner = self.lock();
+        let keys = inner.wake_order().map(|w| Key(w.key)).collect();
+        keys
+    }
+
+    /// Return how many tasks are ahead of the task for `key` in the queue, or `None` if it
+    /// isn't waiting.
+    ///
+    /// A position of 0 means the task is the next one to be woken. The position goes down
+    /// as tasks ahead of it are notified or cancelled, so this is only a snapshot. It takes
+    /// time proportional to the position.
+    pub fn position(&self, key: Key) -> Option<usize> {
+        if self.flags() & WAITING == 0 {
+            return None;
+        }
+        let inner = self.lock();
+        if !inner.is_waiting(key.0) {
+            return None;
+        }
+        let position = inner.wake_order().position(|w| w.key == key.0);
+        position
+    }
+
+    /// Return the number of tasks that can wait at once without allocating more memory.
+    ///
+    /// Other threads may add or remove waiters at any time, so this may already be out of
+    /// date when it is returned.
+    pub fn capacity(&self) -> usize {
+        self.lock().queue.capacity()
+    }
+
+    /// Allocate room for at least `additional` more tasks to wait, on top of those already
+    /// waiting.
+    ///
+    /// This doesn't change the limit of a waitlist created with
+    /// [`Waitlist::with_fixed_capacity`].
+    pub fn reserve(&self, additional: usize) {
+        self.lock().queue.reserve(additional);
+    }
+
+    /// Release memory that isn't needed for the tasks currently waiting, for example after
+    /// a burst of waiters has been notified.
+    ///
+    /// This takes time proportional to the number of waiting tasks, since their storage
+    /// is compacted.
+    pub fn shrink_to_fit(&self) {
+        self.lock().queue.shrink_to_fit();
+    }
+
+    /// Return true if no task is waiting to be notified, without locking.
+    ///
+    /// This is only a hint, meant for skipping work on a hot path: a task may start or
+    /// stop waiting on another thread at any time, so the answer may already be out of
+    /// date when it is returned. It makes the same check that the `notify_*` methods use
+    /// to avoid locking, and the same guarantees apply (see "Memory ordering" above).
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.flags() & WAITING == 0
+    }
+
+    /// Return a future that completes once the task awaiting it is notified.
+    ///
+    /// The future registers its task the first time it is polled, and completes the first
+    /// time it is polled after being notified, so it is roughly a [`WaitHandle`] driven with
+    /// [`WaitHandle::set_context`] and [`WaitHandle::try_finish`]. Dropping it before it
+    /// completes cancels the wait the same way dropping a `WaitHandle` does, passing a
+    /// notification it received on to the next waiter.
+    ///
+    /// # Timeouts
+    ///
+    /// Timers belong to the runtime, so there is no `wait_timeout`. Instead, race the future
+    /// against the runtime's timer with a combinator such as `futures::future::select` or
+    /// `smol::future::or`. When the timer wins, the `WaitFuture` is dropped, so if it was
+    /// notified just as it timed out, the next waiter gets the notification instead:
+    ///
+    /// ```
+    /// # use std::time::Duration;
+    /// # use waitlist::Waitlist;
+    /// # smol::block_on(async {
+    /// let waitlist = Waitlist::new();
+    /// let notified = async {
+    ///     waitlist.wait_async().await;
+    ///     true
+    /// };
+    /// let timed_out = async {
+    ///     smol::Timer::after(Duration::from_millis(10)).await;
+    ///     false
+    /// };
+    /// assert!(!smol::future::or(notified, timed_out).await);
+    /// # });
+    /// ```
+    pub fn wait_async(&self) -> WaitFuture<'_, T> {
+        WaitFuture {
+            handle: self.wait(),
+            registered: false,
+        }
+    }
+
+    /// Return a stream that yields an item each time the task polling it is notified.
+    ///
+    /// The stream registers its task the first time it is polled. After each notification
+    /// it registers again before yielding, so a notification sent while the item is being
+    /// handled isn't missed. Dropping the stream cancels its wait like dropping a
+    /// [`WaitHandle`] does. The stream ends once the waitlist is [shut
+    /// down](Waitlist::shutdown).
+    #[cfg(feature = "stream")]
+    pub fn notified_stream(&self) -> NotifiedStream<'_, T> {
+        NotifiedStream {
+            handle: self.wait(),
+            done: false,
         }
     }
 
@@ -88,25 +693,457 @@ impl Waitlist {
     ///
     /// Returns true if a waker was woken and false if no task was woken (that is, the queue
     /// was empty).
+    ///
+    /// If no task is waiting, this returns without locking. Otherwise it waits for the
+    /// lock like any other method. It doesn't add a spin of its own first: `std`'s and
+    /// `parking_lot`'s mutexes already spin briefly before parking the thread, and the
+    /// `no_std` lock only spins. Whether spinning longer helps depends on the number of
+    /// cores and how contended the waitlist is, so there is no built-in spin count. A caller
+    /// can spin on [`Waitlist::try_notify_one`] before falling back to `notify_one`, and
+    /// `benches/notify_spin.rs` measures the latency of doing that on a multicore machine.
     #[inline]
     pub fn notify_one(&self) -> bool {
-        if self.flags.load(Ordering::Relaxed) & WAITING != 0 {
-            self.lock().notify_first()
+        if self.flags() & (WAITING | SUBSCRIBED) != 0 {
+            let mut inner = self.lock();
+            inner.notify_subscribers();
+            inner.notify_first()
         } else {
             false
         }
     }
 
+    /// Like [`Waitlist::notify_one`], but fail with [`WouldBlock`] instead of waiting if
+    /// the waitlist is locked.
+    ///
+    /// This is an escape hatch for code that must not block, or that may run while the
+    /// waitlist is already locked on the same thread, such as the predicate passed to
+    /// [`Waitlist::notify_while`]. Such code can defer the notification when this fails,
+    /// for example by setting a flag that is checked later. Wakers themselves are never
+    /// woken while the waitlist is locked, so they can use `notify_one` directly. Note that
+    /// waking the task still runs its waker, which may do things that aren't safe in a
+    /// signal handler.
+    pub fn try_notify_one(&self) -> Result<bool, WouldBlock> {
+        if self.flags() & (WAITING | SUBSCRIBED) == 0 {
+            return Ok(false);
+        }
+        let mut inner = self.try_lock().ok_or(WouldBlock)?;
+        inner.notify_subscribers();
+        Ok(inner.notify_first())
+    }
+
+    /// Wake the first waker in the queue, and return its key.
+    ///
+    /// The returned key is the same key that [`WaitHandle::into_key`] returns for the
+    /// woken task's handle, as long as the task hasn't registered again since. This makes
+    /// it possible to keep data about waiting tasks in a side table, and clean it up from
+    /// the notifying side:
+    ///
+    /// ```
+    /// # use std::collections::HashMap;
+    /// # use waitlist::{WaitHandle, Waitlist};
+    /// # let waker = futures_task::noop_waker();
+    /// # let cx = std::task::Context::from_waker(&waker);
+    /// let waitlist = Waitlist::new();
+    /// let mut requests = HashMap::new();
+    ///
+    /// let mut handle = waitlist.wait();
+    /// handle.set_context(&cx);
+    /// let key = handle.into_key();
+    /// requests.insert(key.unwrap(), "first request");
+    ///
+    /// let woken = waitlist.notify_one_key().unwrap();
+    /// assert_eq!(Some("first request"), requests.remove(&woken));
+    /// # assert!(WaitHandle::from_key(&waitlist, key).finish());
+    /// ```
+    ///
+    /// Returns `None` if the queue was empty.
+    pub fn notify_one_key(&self) -> Option<Key> {
+        if self.flags() & WAITING == 0 {
+            return None;
+        }
+        let waiter = self.lock().pop_first()?;
+        waiter.waker.wake();
+        Some(Key(waiter.key))
+    }
+
+    /// Wake the first waker in the queue, and return the token its task registered with
+    /// [`WaitHandle::set_context_with_token`].
+    ///
+    /// This identifies the woken task without allocating, for example to correlate the
+    /// wake with the request it was for in a trace. Tasks that were registered without a
+    /// token have the token 0. Returns `None` if the queue was empty.
+    pub fn notify_one_token(&self) -> Option<u64> {
+        if self.flags() & WAITING == 0 {
+            return None;
+        }
+        let mut inner = self.lock();
+        let waiter = inner.pop_first()?;
+        inner.to_wake.push(waiter.waker);
+        Some(waiter.token)
+    }
+
+    /// Wake every waiting task whose token, from [`WaitHandle::set_context_with_token`],
+    /// satisfies `pred`, and return how many were woken.
+    ///
+    /// This can wake a group of tasks, like all those tagged with the same shard, from
+    /// anywhere in the queue. The matching tasks are woken in the order [`notify_one`]
+    /// would have woken them, and the tasks left waiting keep their order. Tasks that
+    /// were registered without a token have the token 0. The waitlist is locked while
+    /// `pred` runs, so it must not use the waitlist itself.
+    ///
+    /// [`notify_one`]: Waitlist::notify_one
+    pub fn notify_where<F: Fn(u64) -> bool>(&self, pred: F) -> usize {
+        if self.flags() & WAITING == 0 {
+            return 0;
+        }
+        self.lock().notify_where(pred)
+    }
+
+    /// Wake the task waiting with `key`, wherever it is in the queue.
+    ///
+    /// This is meant for waking the task a particular event is for, like the task waiting
+    /// for the response to a request, using a key from [`WaitHandle::key`]. The other
+    /// waiters keep their order. Returns false if `key` doesn't belong to a waiting task,
+    /// for example because it was already notified.
+    pub fn notify_key(&self, key: Key) -> bool {
+        if self.flags() & WAITING == 0 {
+            return false;
+        }
+        self.lock().notify_key(key.0)
+    }
+
+    /// Wake the first `n` waiters in the queue.
+    ///
+    /// This is the same as calling [`Waitlist::notify_one`] `n` times, but only locks the
+    /// waitlist once. Returns the number of waiters woken, which is less than `n` if the
+    /// queue ran out of waiters.
+    pub fn notify_n(&self, n: usize) -> usize {
+        if n == 0 || self.flags() & WAITING == 0 {
+            return 0;
+        }
+        self.lock().notify_n(n, |_| {})
+    }
+
+    /// Wake waiters from the front of the queue for as long as `pred` returns true.
+    ///
+    /// `pred` is called before each waiter is woken, and can check state outside of the
+    /// waitlist, such as the current time for tasks queued in deadline order. It isn't
+    /// called once the queue is empty. The waitlist is locked while `pred` runs, so it must
+    /// not use the waitlist itself. Returns the number of waiters woken.
+    pub fn notify_while<F: FnMut() -> bool>(&self, mut pred: F) -> usize {
+        if self.flags() & WAITING == 0 {
+            return 0;
+        }
+        let mut inner = self.lock();
+        let mut woken = 0;
+        while !inner.queue.is_empty() && pred() {
+            inner.notify_first();
+            woken += 1;
+        }
+        woken
+    }
+
+    /// Wake up to `count` waiters from the front of the queue, and return their keys in the
+    /// order they were woken.
+    ///
+    /// Like [`Waitlist::notify_one_key`], this is meant for keeping a side table keyed by
+    /// [`WaitHandle::into_key`] up to date, but for a whole batch under a single lock.
+    /// Fewer than `count` keys are returned if the queue runs out of waiters.
+    pub fn notify_n_keys(&self, count: usize) -> Vec<Key> {
+        if count == 0 || self.flags() & WAITING == 0 {
+            return Vec::new();
+        }
+        let mut inner = self.lock();
+        let mut keys = Vec::with_capacity(count.min(inner.queue.len()));
+        inner.notify_n(count, |key| keys.push(Key(key)));
+        keys
+    }
+
+    /// Wake the first waker in the queue, and hand `value` to its task.
+    ///
+    /// The woken task can get the value with [`WaitHandle::take_payload`]. If the task
+    /// cancels instead of handling the notification, the value is passed on to the next
+    /// waiter along with the notification, or dropped if there is none. Returns
+    /// `Err(value)` if no task was waiting.
+    pub fn notify_one_with(&self, value: T) -> Result<(), T> {
+        if self.flags() & WAITING == 0 {
+            return Err(value);
+        }
+        self.lock().notify_first_with(value)
+    }
+
+    /// Notify the first waiter in the queue, but hand its waker to `sink` instead of
+    /// waking it.
+    ///
+    /// This is useful for executors that don't want `Waker::wake` to run inline, and
+    /// would rather schedule the task themselves. The waiter is treated exactly as if it
+    /// had been woken by [`Waitlist::notify_one`], so if `sink` never wakes the waker,
+    /// the task stays parked. `sink` is called after the waitlist is unlocked.
+    ///
+    /// Returns true if a waker was passed to `sink`, and false if the queue was empty.
+    pub fn notify_one_via(&self, sink: &dyn Fn(Waker)) -> bool {
+        if self.flags() & WAITING == 0 {
+            return false;
+        }
+        let waiter = self.lock().pop_first();
+        match waiter {
+            Some(waiter) => {
+                sink(waiter.waker);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Wake the first waiter in the queue without removing it.
+    ///
+    /// Unlike [`Waitlist::notify_one`], this wakes the task with [`Waker::wake_by_ref`],
+    /// and leaves it where it is in the queue, still waiting. The woken task doesn't
+    /// count as notified: when it polls again and calls [`WaitHandle::set_context`], it
+    /// keeps its place, and [`WaitHandle::finish`] returns false for it. This suits a
+    /// condition variable, where a task that wakes, finds its condition still false, and
+    /// goes back to sleep shouldn't lose its turn.
+    ///
+    /// Because the waiter stays at the front, the next call to this or any other
+    /// `notify_*` method wakes the same task again, rather than the one behind it.
+    /// Subscribers and child waitlists aren't notified.
+    ///
+    /// Returns true if a task was woken, and false if the queue was empty.
+    pub fn notify_one_by_ref(&self) -> bool {
+        if self.flags() & WAITING == 0 {
+            return false;
+        }
+        self.lock().wake_first_by_ref()
+    }
+
+    /// Wake one or more waiters, depending on how busy the waitlist has recently been.
+    ///
+    /// When notifications keep arriving while tasks are still waiting, this progressively
+    /// wakes more tasks at once to drain the queue faster, as configured by
+    /// [`Waitlist::set_adaptive_policy`]. With the default policy this behaves exactly like
+    /// [`Waitlist::notify_one`].
+    ///
+    /// Returns the number of waiters woken.
+    pub fn notify_adaptive(&self) -> usize {
+        if self.flags() & WAITING == 0 {
+            return 0;
+        }
+        let mut inner = self.lock();
+        let policy = inner.adaptive;
+        let batch = 1 + inner.pressure / policy.threshold.max(1);
+        let mut woken = 0;
+        while woken < batch.min(policy.max_batch.max(1)) && inner.notify_first() {
+            woken += 1;
+        }
+        if inner.queue.is_empty() {
+            inner.pressure /= 2;
+        } else {
+            inner.pressure = inner.pressure.saturating_add(1);
+        }
+        woken
+    }
+
+    /// Set the policy used by [`Waitlist::notify_adaptive`].
+    pub fn set_adaptive_policy(&self, policy: AdaptivePolicy) {
+        self.lock().adaptive = policy;
+    }
+
     /// Wake all wakers in the queue
     ///
-    /// Returns true if at least one waker was woken. False otherwise.
+    /// This also calls `notify_all` on every waitlist linked with [`Waitlist::link_child`].
+    ///
+    /// The wakers are woken once the waitlist is unlocked, so a task that registers again
+    /// while it is being woken, for example from its waker, gets a new key and waits for
+    /// the next notification, instead of being woken again by this one.
+    ///
+    /// Returns true if at least one waker was woken, in this waitlist or one of its
+    /// children. False otherwise.
     #[inline]
     pub fn notify_all(&self) -> bool {
-        if self.flags.load(Ordering::Relaxed) & WAITING != 0 {
-            self.lock().notify_all()
+        self.notify_all_count() > 0
+    }
+
+    /// Like [`Waitlist::notify_all`], but return the number of wakers woken, including
+    /// those of child waitlists.
+    ///
+    /// Returning 0 is equivalent to `notify_all` returning false.
+    pub fn notify_all_count(&self) -> usize {
+        let woken = if self.flags() & (WAITING | SUBSCRIBED) != 0 {
+            let mut inner = self.lock();
+            inner.notify_subscribers();
+            inner.notify_all()
         } else {
-            false
+            0
+        };
+        woken
+            + self
+                .children()
+                .iter()
+                .map(|child| child.notify_all_count())
+                .sum::<usize>()
+    }
+
+    /// Like [`Waitlist::notify_all`], but return the keys of the woken waiters, in the
+    /// order they were woken.
+    ///
+    /// This is meant for keeping a side table keyed by [`WaitHandle::into_key`] up to
+    /// date, like [`Waitlist::notify_n_keys`]. Subscribed channels are notified as with
+    /// `notify_all`, but waitlists linked with [`Waitlist::link_child`] aren't, since the
+    /// keys of their waiters would be meaningless for this waitlist.
+    pub fn notify_all_keys(&self) -> Vec<Key> {
+        if self.flags() & (WAITING | SUBSCRIBED) == 0 {
+            return Vec::new();
         }
+        let mut inner = self.lock();
+        inner.notify_subscribers();
+        let mut keys = Vec::with_capacity(inner.queue.len());
+        inner.notify_n(usize::MAX, |key| keys.push(Key(key)));
+        inner.recompute_min_key();
+        drop(inner);
+        keys
+    }
+
+    /// Remove every waiter from the queue, without waking any of them.
+    ///
+    /// Unlike [`Waitlist::notify_all`], this doesn't call any wakers, so the removed tasks
+    /// aren't polled again because of it. They should find out that they no longer need to
+    /// wait some other way. Their handles behave as if the waits were cancelled with a
+    /// [`CancelToken`](cancel::CancelToken): they are no longer waiting, and finishing or
+    /// cancelling them doesn't count as handling a notification or pass one on.
+    ///
+    /// Tasks that were already notified aren't affected, and still count towards
+    /// [`Waitlist::notify_all_and_await`] until they are handled.
+    pub fn clear(&self) {
+        if self.flags() & WAITING == 0 {
+            return;
+        }
+        self.lock().clear();
+    }
+
+    /// Remove every waiter from the queue, and return their wakers instead of waking them.
+    ///
+    /// The wakers are returned in the order they would have been woken, so that the caller
+    /// can decide when to wake them, for example all at once at the end of a scheduler
+    /// tick. The removed tasks' handles behave the same as after [`Waitlist::clear`]: they
+    /// are no longer waiting, and finishing or cancelling them doesn't count as handling
+    /// a notification, so a task woken with one of the returned wakers should register
+    /// again if it still needs to wait.
+    pub fn drain_wakers(&self) -> Vec<Waker> {
+        if self.flags() & WAITING == 0 {
+            return Vec::new();
+        }
+        let mut inner = self.lock();
+        let mut wakers = Vec::with_capacity(inner.queue.len());
+        while let Some(waiter) = inner.queue.pop_front() {
+            inner.cancelled.insert(waiter.key);
+            wakers.push(waiter.waker);
+        }
+        inner.recompute_min_key();
+        if inner.mode == WaitlistMode::Lifo {
+            wakers.reverse();
+        }
+        drop(inner);
+        wakers
+    }
+
+    /// Cancel every wait, both of the tasks in the queue and of the tasks that were
+    /// notified but haven't handled it yet, without waking any of them.
+    ///
+    /// This is meant for tearing down a waitlist. The queued tasks are removed like with
+    /// [`Waitlist::clear`], and any payloads that weren't taken are dropped. Nothing is
+    /// counted as notified afterwards, so futures from [`Waitlist::notify_all_and_await`]
+    /// complete. Finishing or cancelling the handle of a task that was notified before
+    /// this doesn't pass anything on, as long as no task was notified since. Otherwise it
+    /// can't be told apart from one of the new notifications, and is counted as handling
+    /// one of them.
+    pub fn cancel_all(&self) {
+        if self.flags() & (WAITING | NOTIFIED) == 0 {
+            return;
+        }
+        let mut inner = self.lock();
+        inner.clear();
+        inner.payloads.clear();
+        inner.notified_count = 0;
+        inner.wake_drain_wakers();
+    }
+
+    /// Link `child` to this waitlist, so that [`Waitlist::notify_all`] on this waitlist
+    /// also notifies everything waiting on `child` and its own children.
+    ///
+    /// Returns false without linking anything if this would create a cycle, that is if
+    /// `child` is this waitlist or already has this waitlist as a descendant.
+    pub fn link_child(&self, child: Arc<Waitlist<T>>) -> bool {
+        let _link = LINK_LOCK.lock();
+        let mut stack = alloc::vec![child.clone()];
+        while let Some(wl) = stack.pop() {
+            if core::ptr::eq(self, &*wl) {
+                return false;
+            }
+            stack.extend(wl.children());
+        }
+        self.children.lock().push(child);
+        true
+    }
+
+    fn children(&self) -> Vec<Arc<Waitlist<T>>> {
+        self.children.lock().clone()
+    }
+
+    /// Wake all wakers in the queue, and return a future that completes once every
+    /// notified task has been handled.
+    ///
+    /// A notified task is handled once it calls [`WaitHandle::finish`], cancels (including
+    /// by being dropped), or registers a new context and goes back to waiting. This includes
+    /// tasks that were notified before this call and haven't been handled yet. Note that a
+    /// cancelled task passes its notification on to the next waiter, if there is one, which
+    /// then also needs to be handled.
+    ///
+    /// The waiters are woken when this is called, not when the future is first polled.
+    pub fn notify_all_and_await(&self) -> AllHandled<'_, T> {
+        self.notify_all();
+        AllHandled { waitlist: self }
+    }
+
+    /// Close the waitlist, wake all waiters, and return a future that completes once
+    /// every notified task has been handled.
+    ///
+    /// After this is called the waitlist doesn't accept new waiters. Registering with
+    /// [`WaitHandle::set_context`] (or any of its variants) instead wakes the task
+    /// immediately and leaves the handle not pending, the same as a handle that was
+    /// notified and then finished. Tokens registered with [`Waitlist::register_select`]
+    /// fire immediately. The `notify_*` methods keep working, but there is nothing left in
+    /// the queue to notify. A closed waitlist can't be reopened.
+    ///
+    /// The returned future completes once the queue is empty and every notified task was
+    /// handled, as for [`Waitlist::notify_all_and_await`].
+    pub fn shutdown(&self) -> AllHandled<'_, T> {
+        self.lock().closed = true;
+        self.notify_all_and_await()
+    }
+
+    /// Return true if [`Waitlist::shutdown`] was called.
+    pub fn is_closed(&self) -> bool {
+        self.lock().closed
+    }
+
+    /// Return a marker for the current point in the waitlist's history.
+    ///
+    /// Pass it to [`Waitlist::notify_since`] to wake only tasks that started waiting after
+    /// this call.
+    pub fn marker(&self) -> usize {
+        self.lock().next_key
+    }
+
+    /// Wake all waiters that were registered after `marker` was created with
+    /// [`Waitlist::marker`], leaving older waiters in the queue.
+    ///
+    /// Waiters are woken in the order they were registered. Returns the number of waiters
+    /// woken.
+    pub fn notify_since(&self, marker: usize) -> usize {
+        if self.flags() & WAITING == 0 {
+            return 0;
+        }
+        self.lock().notify_since(marker)
     }
 
     /// Wake the next waker, unless it has already been notified.
@@ -114,9 +1151,14 @@ impl Waitlist {
     /// This ensures that at least one waker has been notified, but avoid waking
     /// multiple wakers if multiple events occur before the first task has marked the
     /// handle as completed.
+    ///
+    /// A notified task that finishes (or waits again) and then checks for events can't
+    /// miss one: if `notify_any` still counts the task as notified, the task sees the
+    /// event when it checks, as described in "Memory ordering" in the docs for
+    /// [`Waitlist`]. If nothing counts as notified, the oldest waiting task is woken.
     #[inline]
     pub fn notify_any(&self) -> bool {
-        let flags = self.flags.load(Ordering::Relaxed);
+        let flags = self.flags();
         if flags & NOTIFIED == 0 && flags & WAITING != 0 {
             let mut inner = self.lock();
             // We need check the notified_count, because
@@ -131,9 +1173,213 @@ impl Waitlist {
             false
         }
     }
+
+    /// Wake enough waiters that at least `n` tasks are notified and haven't handled it
+    /// yet.
+    ///
+    /// This generalizes [`Waitlist::notify_any`]: tasks that were already notified count
+    /// towards `n`, and only the difference is woken from the front of the queue, or fewer
+    /// if the queue runs out. Returns the number of waiters newly woken.
+    pub fn notify_any_n(&self, n: usize) -> usize {
+        if n == 0 || self.flags() & WAITING == 0 {
+            return 0;
+        }
+        let mut inner = self.lock();
+        let needed = n.saturating_sub(inner.notified_count);
+        inner.notify_n(needed, |_| {})
+    }
+
+    /// Run [`WaitHandle::try_finish`] on each of `handles`, while only locking the waitlist
+    /// once.
+    ///
+    /// Handles that are still waiting have their waker updated from `cx`, and handles
+    /// that were notified are marked as finished. Returns the number of handles for which
+    /// `try_finish` would have returned true, that is the number of handles that are now
+    /// finished.
+    ///
+    /// # Panics
+    ///
+    /// Panics if any of the handles belongs to a different waitlist.
+    pub fn try_finish_all(&self, handles: &mut [WaitHandle<'_, T>], cx: &mut Context<'_>) -> usize {
+        let mut inner = self.lock();
+        let mut finished = 0;
+        for handle in handles {
+            assert!(
+                core::ptr::eq(self, handle.waitlist),
+                "handle belongs to a different waitlist"
+            );
+            if let Some(key) = handle.key {
+                if inner.update_if_pending(key, cx) {
+                    continue;
+                }
+                handle.key = None;
+                handle.keep_payload(&mut inner, key);
+            }
+            finished += 1;
+        }
+        finished
+    }
+
+    /// Remove waiters whose task is gone, as decided by `is_live`, without waking them.
+    ///
+    /// A queued waker may keep its task alive in some runtimes, even after everything else
+    /// has let go of it. `Waker` has no way to tell whether its task still exists, so
+    /// `is_live` is called with each waiting task's waker to decide, usually by keeping a
+    /// side table of the tasks being torn down and comparing them with
+    /// [`Waker::will_wake`]. Waiters it returns false for are removed and their wakers
+    /// dropped. The waitlist is locked while `is_live` runs, so it must not use the
+    /// waitlist itself.
+    ///
+    /// Only tasks that are still waiting are checked. A removed task's handle behaves as
+    /// if its wait was cancelled with a [`CancelToken`](cancel::CancelToken), so dropping
+    /// it later, as its task is torn down, doesn't count as handling a notification.
+    /// Returns the number of waiters removed.
+    pub fn retain_live<F: Fn(&Waker) -> bool>(&self, is_live: F) -> usize {
+        if self.flags() & WAITING == 0 {
+            return 0;
+        }
+        let mut inner = self.lock();
+        let inner = &mut *inner;
+        let before = inner.queue.len();
+        let cancelled = &mut inner.cancelled;
+        inner.queue.retain(|w| {
+            let live = is_live(&w.waker);
+            if !live {
+                cancelled.insert(w.key);
+            }
+            live
+        });
+        inner.recompute_min_key();
+        before - inner.queue.len()
+    }
+
+    /// Remove waiters whose waker hasn't been set within the last `window`, without waking
+    /// them.
+    ///
+    /// This is meant for cleaning up waiters abandoned by tasks that lost their handle's
+    /// key (see [`WaitHandle::into_key`]). The waker is refreshed every time the handle
+    /// calls [`WaitHandle::set_context`] or a similar method. A waiter that hasn't been
+    /// registered again within `window` is removed whether or not its task is still alive,
+    /// and a task that is parked until it is notified is usually not polled, so it isn't
+    /// woken again unless something else wakes it. Only use a `window` longer than any
+    /// task should wait without being polled.
+    ///
+    /// Like with [`Waitlist::retain_live`], a removed task's handle behaves as if its wait
+    /// was cancelled with a [`CancelToken`](cancel::CancelToken), so finishing or dropping
+    /// it later doesn't count as handling a notification. Returns the number of waiters
+    /// removed.
+    #[cfg(feature = "timing")]
+    pub fn gc_stale(&self, window: Duration) -> usize {
+        let mut inner = self.lock();
+        let inner = &mut *inner;
+        let now = inner.clock.now();
+        let before = inner.queue.len();
+        let cancelled = &mut inner.cancelled;
+        inner.queue.retain(|w| {
+            let fresh = now.saturating_duration_since(w.refreshed) < window;
+            if !fresh {
+                cancelled.insert(w.key);
+            }
+            fresh
+        });
+        inner.recompute_min_key();
+        before - inner.queue.len()
+    }
+
+    /// How long the waiter at the front of the queue has been waiting, or `None` if
+    /// nothing is waiting.
+    ///
+    /// This only looks at the waiter at the front of the queue, so it takes constant time.
+    /// Waiters are queued in the order they registered, so that is usually the oldest one,
+    /// also for a LIFO waitlist, where it is the one notified last. It isn't after
+    /// [`WaitHandle::set_context_front`] put a newer waiter in front of the others, or, in
+    /// [`WaitlistMode::FairRequeue`], after a re-registered waiter was put back in front of
+    /// newer ones, which restarts its age. Refreshing a waker with
+    /// [`WaitHandle::set_context`] doesn't reset this age. A steadily growing value usually
+    /// means that nothing is notifying the waitlist.
+    #[cfg(feature = "timing")]
+    pub fn oldest_wait_age(&self) -> Option<Duration> {
+        if self.flags() & WAITING == 0 {
+            return None;
+        }
+        let inner = self.lock();
+        let parked = inner.queue.front()?.parked;
+        Some(inner.clock.now().saturating_duration_since(parked))
+    }
+
+    /// How long the waiter that the next [`Waitlist::notify_one`] would wake has been
+    /// waiting, measured from when it was added to the queue.
+    ///
+    /// For a FIFO waitlist this is the same as [`Waitlist::oldest_wait_age`]. It is meant for
+    /// picking which of several waitlists to notify, by comparing their front waiters. The value is
+    /// only a snapshot: by the time `notify_one` is called, the front waiter may have been
+    /// notified or removed by another thread.
+    #[cfg(feature = "timing")]
+    pub fn front_wait_age(&self) -> Option<Duration> {
+        if self.flags() & WAITING == 0 {
+            return None;
+        }
+        let inner = self.lock();
+        let parked = inner.wake_order().next()?.parked;
+        Some(inner.clock.now().saturating_duration_since(parked))
+    }
+
+    /// Return the counters accumulated since the last call, and reset them to zero.
+    ///
+    /// This is meant for reporting metrics at regular intervals. Each counter is read and
+    /// reset atomically, but not all of them at once, so an operation that happens
+    /// concurrently may be split across two intervals, for example by counting a waiter
+    /// as inserted in one and as woken in the next.
+    pub fn take_stats(&self) -> WaitlistStats {
+        WaitlistStats {
+            total_woken: self.stats.woken.swap(0, Ordering::Relaxed),
+            total_inserted: self.stats.inserted.swap(0, Ordering::Relaxed),
+            total_cancelled: self.stats.cancelled.swap(0, Ordering::Relaxed),
+        }
+    }
+
+    /// Return the number of waiters added and notified over the lifetime of the waitlist.
+    ///
+    /// Unlike [`Waitlist::take_stats`] this doesn't reset anything, so the totals only
+    /// grow, which suits monitoring systems that compute rates themselves. The totals wrap
+    /// around on overflow. Like `take_stats`, the counts are read one at a time, so they
+    /// may not all include an operation that happens concurrently.
+    pub fn stats(&self) -> WaitlistTotals {
+        WaitlistTotals {
+            enqueued_total: self.stats.inserted_total.load(Ordering::Relaxed),
+            woken_total: self.stats.woken_total.load(Ordering::Relaxed),
+            currently_waiting: self.len(),
+        }
+    }
+
+    /// Create a waker that calls [`Waitlist::notify_one`] on `target` when woken.
+    ///
+    /// Registering this waker on another waitlist forwards that waitlist's notifications
+    /// to `target`, which can be used to chain waitlists together. Don't register it on
+    /// `target` itself: waking it notifies `target` again, so every notification that
+    /// reaches it loops back into `target` and wakes the next waiter as well.
+    pub fn forwarding_waker(target: &Arc<Waitlist<T>>) -> Waker
+    where
+        T: Send + 'static,
+    {
+        Waker::from(Arc::new(Forward(target.clone())))
+    }
+
+    /// Subscribe `tx` to receive a message every time this waitlist is notified.
+    ///
+    /// Subscribers are kept separately from the queue of waiting tasks, and stay subscribed
+    /// until their receiver is dropped. Every call to [`Waitlist::notify_one`] or
+    /// [`Waitlist::notify_all`] sends one `()` to every subscriber, in addition to waking
+    /// waiting tasks as usual. Subscribers don't take the place of a waiting task, so
+    /// `notify_one` still wakes a task if one is waiting, and the return values only count
+    /// woken tasks. The other notify methods don't send anything to subscribers.
+    #[cfg(feature = "channel")]
+    pub fn subscribe(&self, tx: UnboundedSender<()>) {
+        self.lock().subscribers.push(tx);
+    }
 }
 
-impl fmt::Debug for Waitlist {
+impl<T> fmt::Debug for Waitlist<T> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_struct("Waitlist")
             .field("flags", &self.flags)
@@ -141,7 +1387,7 @@ impl fmt::Debug for Waitlist {
     }
 }
 
-impl WaitHandle<'_> {
+impl<T> WaitHandle<'_, T> {
     /// Mark this task as completed.
     ///
     /// If this handle still has a waker on the queue,
@@ -150,7 +1396,10 @@ impl WaitHandle<'_> {
     #[inline]
     pub fn finish(&mut self) -> bool {
         if let Some(key) = self.key.take() {
-            self.waitlist.lock().remove(key)
+            let mut inner = self.waitlist.lock();
+            let notified = inner.remove(key);
+            self.keep_payload(&mut inner, key);
+            notified
         } else {
             false
         }
@@ -170,14 +1419,205 @@ impl WaitHandle<'_> {
         }
     }
 
+    /// Mark this task as completed, and wake the next task in line.
+    ///
+    /// The next task is woken whether or not this one had been notified, which makes this
+    /// the handoff for something like a ticket lock: the task holding the lock calls this
+    /// when it releases it, whether it got the lock by waiting or not. That differs from
+    /// [`WaitHandle::cancel`], which only passes on a notification this task received
+    /// and hadn't handled, and from [`WaitHandle::finish`], which never wakes anyone.
+    ///
+    /// A payload sent to this task is kept, as with `finish`. Returns true if a task was
+    /// woken, and false if no other task was waiting.
+    pub fn finish_and_notify_next(&mut self) -> bool {
+        let mut inner = self.waitlist.lock();
+        if let Some(key) = self.key.take() {
+            self.keep_payload(&mut inner, key);
+            inner.remove_and_notify_next(key)
+        } else {
+            inner.notify_first()
+        }
+    }
+
+    /// Return the handle to the state it had when it was created by [`Waitlist::wait`], so
+    /// that it can be reused for a new wait.
+    ///
+    /// A wait still in progress is cancelled first, as with [`WaitHandle::cancel`], and
+    /// the return value is the same. Any payload that wasn't taken is dropped, and the
+    /// debounce key from [`WaitHandle::set_context_debounce`] is forgotten. The next
+    /// registration always adds the task to the back of the queue with a new key.
+    pub fn reset(&mut self) -> bool {
+        let passed_on = self.cancel();
+        if self.debounce.is_some() {
+            self.release_debounce(&mut self.waitlist.lock());
+        }
+        self.payload = None;
+        passed_on
+    }
+
+    /// Register the waker from `cx` to be woken when this task is notified.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the waitlist is at the waiter limit set by [`Waitlist::with_fixed_capacity`]
+    /// or [`Waitlist::with_max_waiters`]. Use [`WaitHandle::try_set_context`] to handle that case
+    /// instead.
     #[inline]
     pub fn set_context(&mut self, cx: &Context) {
-        let key = if let Some(key) = self.key {
-            self.waitlist.lock().update(key, cx)
+        self.try_set_context(cx).expect("waitlist is full")
+    }
+
+    /// Like [`WaitHandle::set_context`], but return an error instead of panicking
+    /// if the waitlist is full.
+    ///
+    /// The limit only applies when the task needs a new place in the queue. Updating
+    /// the waker of a task that is still waiting always succeeds. If this fails, the
+    /// handle is no longer pending.
+    pub fn try_set_context(&mut self, cx: &Context) -> Result<(), WaitlistFull> {
+        self.try_register(Cow::Borrowed(cx.waker()))
+    }
+
+    /// Like [`WaitHandle::set_context`], but take a waker the caller already owns, instead
+    /// of cloning one from a `Context`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the waitlist is at the waiter limit set by [`Waitlist::with_fixed_capacity`]
+    /// or [`Waitlist::with_max_waiters`]. Use [`WaitHandle::try_set_waker`] to handle that case
+    /// instead.
+    #[inline]
+    pub fn set_waker(&mut self, waker: Waker) {
+        self.try_set_waker(waker).expect("waitlist is full")
+    }
+
+    /// Like [`WaitHandle::try_set_context`], but take a waker the caller already owns.
+    pub fn try_set_waker(&mut self, waker: Waker) -> Result<(), WaitlistFull> {
+        self.try_register(Cow::Owned(waker))
+    }
+
+    fn try_register(&mut self, waker: Cow<'_, Waker>) -> Result<(), WaitlistFull> {
+        let mut inner = self.waitlist.lock();
+        if self.reject_closed(&mut inner, &waker) {
+            return Ok(());
+        }
+        self.register(&mut inner, waker)
+    }
+
+    /// Like [`WaitHandle::set_context`], but put the task at the front of the queue, so
+    /// that it is the next one woken.
+    ///
+    /// If the task is still waiting, it moves to the front and keeps its key. Otherwise it
+    /// is added at the front with a new key. Tasks registered this way after it go in front
+    /// of it in turn. For a waitlist in [`WaitlistMode::Lifo`], where the next task woken is
+    /// the newest one, this is the same as `set_context`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the waitlist is at the waiter limit set by [`Waitlist::with_fixed_capacity`]
+    /// or [`Waitlist::with_max_waiters`]. Use [`WaitHandle::try_set_context_front`] to handle
+    /// that case instead.
+    pub fn set_context_front(&mut self, cx: &Context<'_>) {
+        self.try_set_context_front(cx).expect("waitlist is full")
+    }
+
+    /// Like [`WaitHandle::set_context_front`], but return an error instead of panicking if
+    /// the waitlist is full.
+    pub fn try_set_context_front(&mut self, cx: &Context<'_>) -> Result<(), WaitlistFull> {
+        let mut inner = self.waitlist.lock();
+        if self.reject_closed(&mut inner, cx.waker()) {
+            return Ok(());
+        }
+        if let Some(old) = self.key.take() {
+            if inner.move_to_front(old, cx.waker()) {
+                self.key = Some(old);
+                return Ok(());
+            }
+            // handle the notification, and register again below
+            inner.remove(old);
+            self.keep_payload(&mut inner, old);
+        }
+        if inner.is_full() {
+            return Err(WaitlistFull);
+        }
+        self.key = Some(inner.insert_front(cx.waker().clone()));
+        Ok(())
+    }
+
+    /// Like [`WaitHandle::set_context`], but also attach `token` to the task, for
+    /// [`Waitlist::notify_one_token`] to return when it wakes the task.
+    ///
+    /// The token stays with the task while it waits, even if its waker is updated with
+    /// `set_context`. A task that registers again after being notified has the token 0,
+    /// unless it passes its token to this method again.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the waitlist is at the waiter limit set by [`Waitlist::with_fixed_capacity`]
+    /// or [`Waitlist::with_max_waiters`].
+    pub fn set_context_with_token(&mut self, cx: &Context<'_>, token: u64) {
+        let mut inner = self.waitlist.lock();
+        if self.reject_closed(&mut inner, cx.waker()) {
+            return;
+        }
+        self.register(&mut inner, Cow::Borrowed(cx.waker()))
+            .expect("waitlist is full");
+        if let Some(w) = inner.queue.get_mut(self.key.unwrap()) {
+            w.token = token;
+        }
+    }
+
+    /// Update the waker for this handle, or add it to the queue again if it was notified.
+    fn register(
+        &mut self,
+        inner: &mut Inner<T>,
+        waker: Cow<'_, Waker>,
+    ) -> Result<(), WaitlistFull> {
+        let key = if let Some(old) = self.key.take() {
+            let key = inner.try_update(old, waker);
+            // only notified waiters have payloads, so this doesn't take anything if the
+            // waker was just updated, even though a requeued waiter keeps its key too
+            self.keep_payload(inner, old);
+            key?
         } else {
-            self.waitlist.lock().insert(cx)
+            inner.try_insert(waker)?
         };
         self.key = Some(key);
+        Ok(())
+    }
+
+    /// If the waitlist was shut down, finish this handle and wake `waker` instead of
+    /// registering it, and return true.
+    fn reject_closed(&mut self, inner: &mut Inner<T>, waker: &Waker) -> bool {
+        if !inner.closed {
+            return false;
+        }
+        if let Some(key) = self.key.take() {
+            inner.remove(key);
+            self.keep_payload(inner, key);
+        }
+        inner.to_wake.push(waker.clone());
+        true
+    }
+
+    /// Move the payload sent to the waiter for `key`, if any, into the handle, once the
+    /// handle no longer uses that key.
+    fn keep_payload(&mut self, inner: &mut Inner<T>, key: usize) {
+        if let Some(payload) = inner.take_payload(key) {
+            self.payload = Some(payload);
+        }
+    }
+
+    /// Take the value sent to this task with [`Waitlist::notify_one_with`].
+    ///
+    /// This can be called either before or after the notification is handled with
+    /// [`WaitHandle::finish`] or similar, but only returns the value once. Returns `None`
+    /// if the task wasn't notified with a value, or the value was already taken.
+    pub fn take_payload(&mut self) -> Option<T> {
+        if let Some(payload) = self.payload.take() {
+            return Some(payload);
+        }
+        let key = self.key?;
+        self.waitlist.lock().take_payload(key)
     }
 
     /// Return true if the WaitHandle has been polled at least once, and has not been
@@ -186,6 +1626,34 @@ impl WaitHandle<'_> {
         self.key.is_some()
     }
 
+    /// Return true if this task was notified, and hasn't handled the notification yet.
+    ///
+    /// Unlike [`WaitHandle::try_finish`], this only looks: the handle stays pending, and
+    /// the notification still has to be handled with `finish` or one of the other methods,
+    /// or passed on with `cancel`. A wait that was removed without a notification, by a
+    /// [`CancelToken`](cancel::CancelToken) or [`Waitlist::clear`], doesn't count as
+    /// notified.
+    pub fn is_notified(&self) -> bool {
+        let Some(key) = self.key else {
+            return false;
+        };
+        if self.waitlist.flags() & NOTIFIED == 0 {
+            return false;
+        }
+        let inner = self.waitlist.lock();
+        !inner.is_waiting(key) && !inner.cancelled.contains(&key)
+    }
+
+    /// Return the handle's current key, the same value [`WaitHandle::into_key`] would
+    /// return, without giving up the handle.
+    ///
+    /// The key changes when the handle registers again after being notified, unless the
+    /// waitlist uses [`WaitlistMode::FairRequeue`], and is `None` when the handle isn't
+    /// pending.
+    pub fn key(&self) -> Option<Key> {
+        self.key.map(Key)
+    }
+
     /// Mark as finished if this was notified, otherwise update the context.
     ///
     /// This is roughly equivalent to
@@ -205,163 +1673,861 @@ impl WaitHandle<'_> {
     /// but operates atomically on the waitlist.
     pub fn try_finish(&mut self, cx: &mut Context<'_>) -> bool {
         if let Some(key) = self.key {
-            if self.waitlist.lock().update_if_pending(key, cx) {
+            let mut inner = self.waitlist.lock();
+            if inner.update_if_pending(key, cx) {
                 return false;
             } else {
                 self.key = None;
+                self.keep_payload(&mut inner, key);
             }
         }
         true
     }
 
-    /// Convert into a key that can later be used with `from_key` to convert back into a `WaitHandle`.
-    pub fn into_key(self) -> Option<usize> {
-        let key = self.key;
-        mem::forget(self);
-        key
+    /// Like [`WaitHandle::try_finish`], but return a `Poll`, for use in `Future::poll`.
+    ///
+    /// Returns `Poll::Ready(())` if the task was notified, and marks the wait as finished.
+    /// Otherwise the waker is updated from `cx`, so that it is the one woken, and this
+    /// returns `Poll::Pending`. A handle that isn't pending, because it was never
+    /// registered or already finished, is ready, so register it with
+    /// [`WaitHandle::set_context`] before polling it for the first time.
+    pub fn poll_finished(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        if self.try_finish(cx) {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+
+    /// Poll a future that waits on this handle until `check` returns `Some`.
+    ///
+    /// `check` is run first, and if the task is still queued, the waker is just kept up to
+    /// date, since any notification after the check will wake it. If the task was notified,
+    /// or hasn't registered yet, it registers before running `check` again, so that a
+    /// notification sent in between can't be missed. The wait is finished once `check`
+    /// succeeds.
+    pub(crate) fn poll_until<R>(
+        &mut self,
+        cx: &mut Context<'_>,
+        mut check: impl FnMut() -> Option<R>,
+    ) -> Poll<R> {
+        if let Some(ready) = check() {
+            self.finish();
+            return Poll::Ready(ready);
+        }
+        if self.is_pending() && !self.try_finish(cx) {
+            return Poll::Pending;
+        }
+        self.set_context(cx);
+        match check() {
+            Some(ready) => {
+                self.finish();
+                Poll::Ready(ready)
+            }
+            None => Poll::Pending,
+        }
+    }
+
+    /// Convert into a key that can later be used with `from_key` to convert back into a `WaitHandle`.
+    ///
+    /// The waiter stays in the queue until the key is converted back with `from_key` and
+    /// the resulting handle is finished, cancelled or dropped. If the key is lost instead,
+    /// its waker stays in the queue until it is notified, which for a long-lived waitlist
+    /// that is rarely notified amounts to a leak. With the `timing` feature,
+    /// [`Waitlist::gc_stale`] can clean up such waiters.
+    ///
+    /// A payload from [`Waitlist::notify_one_with`] that the handle already received isn't
+    /// kept with the key, and is dropped. Call [`WaitHandle::take_payload`] first to keep
+    /// it. A payload the handle hasn't seen yet stays with the waitlist, and can be taken
+    /// from the handle that `from_key` returns.
+    pub fn into_key(mut self) -> Option<Key> {
+        let key = self.key.map(Key);
+        if self.debounce.is_some() {
+            self.release_debounce(&mut self.waitlist.lock());
+        }
+        drop(self.payload.take());
+        mem::forget(self);
+        key
+    }
+
+    // should this be unsafe?
+    /// Create a `WaitHandle` for a `Waitlist` using a key that was previously acquired from
+    /// `into_key`.
+    ///
+    /// For this to work as expected, `key` should be a key returned by a previous call to `into_key`
+    /// on a `WaitHandle` that was created from the same `waitlist`. This takes ownership of the wait
+    /// entry for this key.
+    ///
+    /// Using a key again after its wait was completed doesn't panic, but it can't be told
+    /// apart from a task that was notified and hasn't handled it yet. If other notified
+    /// tasks are outstanding, the stale handle is counted as one of them, which can make
+    /// [`Waitlist::notify_all_and_await`] complete early.
+    ///
+    /// You should avoid using this if possible, but in some cases it is necessary to avoid
+    /// self-reference.
+    pub fn from_key(waitlist: &Waitlist<T>, key: Option<Key>) -> WaitHandle<'_, T> {
+        WaitHandle {
+            waitlist,
+            key: key.map(Key::into_raw),
+            debounce: None,
+            payload: None,
+        }
+    }
+
+    /// Like [`WaitHandle::from_key`], but check that `key` belongs to a task that is still
+    /// waiting on `waitlist` first.
+    ///
+    /// A key is accepted if it is `None`, if its waiter is still in the queue, or if it
+    /// was removed by a [`CancelToken`](cancel::CancelToken) or [`Waitlist::clear`] and
+    /// its handle hasn't seen that yet. Otherwise this returns [`KeyError`]. That includes
+    /// the key of a task that was notified but hasn't handled the notification, since it
+    /// can't be told apart from a key that was already used. Such keys can only be
+    /// converted back with `from_key`.
+    pub fn try_from_key(
+        waitlist: &Waitlist<T>,
+        key: Option<Key>,
+    ) -> Result<WaitHandle<'_, T>, KeyError> {
+        if let Some(Key(raw)) = key {
+            let inner = waitlist.lock();
+            if !inner.is_waiting(raw) && !inner.cancelled.contains(&raw) {
+                return Err(KeyError);
+            }
+        }
+        Ok(WaitHandle::from_key(waitlist, key))
+    }
+}
+
+/// Future returned by [`Waitlist::notify_all_and_await`].
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct AllHandled<'a, T = ()> {
+    waitlist: &'a Waitlist<T>,
+}
+
+impl<T> Future for AllHandled<'_, T> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let mut inner = self.waitlist.lock();
+        if inner.notified_count == 0 {
+            return Poll::Ready(());
+        }
+        if !inner.drain_wakers.iter().any(|w| w.will_wake(cx.waker())) {
+            inner.drain_wakers.push(cx.waker().clone());
+        }
+        Poll::Pending
+    }
+}
+
+impl<T> fmt::Debug for AllHandled<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("AllHandled").finish()
+    }
+}
+
+/// Future returned by [`Waitlist::wait_async`].
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct WaitFuture<'a, T = ()> {
+    handle: WaitHandle<'a, T>,
+    registered: bool,
+}
+
+// The payload in the handle is never pinned
+impl<T> Unpin for WaitFuture<'_, T> {}
+
+impl<T> Future for WaitFuture<'_, T> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let this = self.get_mut();
+        if !this.registered {
+            this.registered = true;
+            this.handle.set_context(cx);
+            Poll::Pending
+        } else {
+            this.handle.poll_finished(cx)
+        }
+    }
+}
+
+impl<T> fmt::Debug for WaitFuture<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("WaitFuture")
+            .field("key", &self.handle.key())
+            .finish()
+    }
+}
+
+/// Stream returned by [`Waitlist::notified_stream`].
+#[cfg(feature = "stream")]
+#[must_use = "streams do nothing unless polled"]
+pub struct NotifiedStream<'a, T = ()> {
+    handle: WaitHandle<'a, T>,
+    done: bool,
+}
+
+// The payload in the handle is never pinned
+#[cfg(feature = "stream")]
+impl<T> Unpin for NotifiedStream<'_, T> {}
+
+#[cfg(feature = "stream")]
+impl<T> Stream for NotifiedStream<'_, T> {
+    type Item = ();
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<()>> {
+        let this = self.get_mut();
+        if this.done {
+            return Poll::Ready(None);
+        }
+        let notified = if this.handle.is_pending() {
+            if !this.handle.try_finish(cx) {
+                return Poll::Pending;
+            }
+            true
+        } else {
+            false
+        };
+        if this.handle.waitlist.is_closed() {
+            this.done = true;
+            return Poll::Ready(notified.then_some(()));
+        }
+        this.handle.set_context(cx);
+        if notified {
+            Poll::Ready(Some(()))
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+#[cfg(feature = "stream")]
+impl<T> fmt::Debug for NotifiedStream<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("NotifiedStream")
+            .field("key", &self.handle.key())
+            .field("done", &self.done)
+            .finish()
     }
+}
 
-    // should this be unsafe?
-    /// Create a `WaitHandle` for a `Waitlist` using a key that was previously acquired from
-    /// `into_key`.
-    ///
-    /// For this to work as expected, `key` should be a key returned by a previous call to `into_key`
-    /// on a `WaitHandle` that was created from the same `waitlist`. This takes ownership of the wait
-    /// entry for this key.
-    ///
-    /// You should avoid using this if possible, but in some cases it is necessary to avoid
-    /// self-reference.
-    pub fn from_key(waitlist: &Waitlist, key: Option<usize>) -> WaitHandle<'_> {
-        WaitHandle { waitlist, key }
+/// Error returned when registering a task on a full fixed-capacity [`Waitlist`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WaitlistFull;
+
+impl fmt::Display for WaitlistFull {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("waitlist is full")
+    }
+}
+
+impl Error for WaitlistFull {}
+
+/// Error returned by [`Waitlist::try_notify_one`] when the waitlist is locked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WouldBlock;
+
+impl fmt::Display for WouldBlock {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("waitlist is locked")
+    }
+}
+
+impl Error for WouldBlock {}
+
+/// Error returned by [`WaitHandle::try_from_key`] for a key that isn't waiting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyError;
+
+impl fmt::Display for KeyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("key doesn't belong to a waiting task")
     }
 }
 
-impl<'a> Drop for WaitHandle<'a> {
+impl Error for KeyError {}
+
+impl<T> Drop for WaitHandle<'_, T> {
     fn drop(&mut self) {
+        if self.key.is_none() && self.debounce.is_none() {
+            return;
+        }
+        let mut inner = self.waitlist.lock();
         if let Some(key) = self.key {
-            self.waitlist.lock().cancel(key);
+            inner.cancel(key);
         }
+        self.release_debounce(&mut inner);
     }
 }
 
-unsafe impl Send for Waitlist {}
-unsafe impl Sync for Waitlist {}
+impl<T> fmt::Debug for WaitHandle<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("WaitHandle")
+            .field("key", &self.key())
+            .field("pending", &self.is_pending())
+            .finish()
+    }
+}
 
-impl Default for Waitlist {
+impl<T> Default for Waitlist<T> {
     fn default() -> Self {
-        Self::new()
+        Self::with_limit(0, None, WaitlistMode::Fifo)
     }
 }
 
-impl Inner {
+impl<T> Inner<T> {
+    fn new(cap: usize, limit: Option<usize>, mode: WaitlistMode) -> Inner<T> {
+        Inner {
+            queue: WaiterQueue::with_capacity(cap),
+            payloads: Map::new(),
+            notified_count: 0,
+            min_key: 0,
+            next_key: 0,
+            unordered: false,
+            limit,
+            mode,
+            drain_wakers: Vec::new(),
+            adaptive: AdaptivePolicy::default(),
+            pressure: 0,
+            debounce: Map::new(),
+            cancelled: Set::new(),
+            closed: false,
+            stats: WaitlistStats::default(),
+            to_wake: Wakers::default(),
+            #[cfg(feature = "timing")]
+            clock: Box::new(SystemClock),
+            #[cfg(feature = "channel")]
+            subscribers: Vec::new(),
+        }
+    }
+
     fn is_in_waiting_range(&self, key: usize) -> bool {
-        // the part after `||` is to deal with if the key wraps around
-        key >= self.min_key || (self.next_key < self.min_key && key < self.next_key)
+        // keys in the range `min_key..next_key`, taking into account that the range may wrap
+        // around
+        key.wrapping_sub(self.min_key) < self.next_key.wrapping_sub(self.min_key)
+    }
+
+    /// Return true if the waiter for `key` is still in the queue.
+    fn is_waiting(&self, key: usize) -> bool {
+        self.is_in_waiting_range(key) && self.queue.contains(key)
     }
 
     fn insert(&mut self, cx: &Context<'_>) -> usize {
+        self.insert_waker(cx.waker().clone())
+    }
+
+    fn insert_waker(&mut self, waker: Waker) -> usize {
+        self.stats.total_inserted += 1;
+        let key = self.take_key();
+        self.queue.push_back(self.new_waiter(key, waker));
+        key
+    }
+
+    /// Create a waiter that was parked just now.
+    fn new_waiter(&self, key: usize, waker: Waker) -> Waiter {
+        #[cfg(feature = "timing")]
+        let now = self.clock.now();
+        Waiter {
+            key,
+            waker,
+            debounce: None,
+            token: 0,
+            #[cfg(feature = "timing")]
+            parked: now,
+            #[cfg(feature = "timing")]
+            refreshed: now,
+        }
+    }
+
+    /// Hand out the next key.
+    fn take_key(&mut self) -> usize {
         let key = self.next_key;
-        let waker = cx.waker().clone();
-        self.next_key = self.next_key.wrapping_add(1);
-        self.queue.push_back(Waiter { key, waker });
+        self.next_key = key.wrapping_add(1);
+        // If the queue was empty, `min_key` is `key`. Otherwise, once `next_key` wraps
+        // around to `min_key`, the range of waiting keys is empty, and the next key handed
+        // out would be the one the oldest waiter still has.
+        debug_assert_ne!(
+            self.next_key, self.min_key,
+            "a task stayed parked while usize::MAX other waiters registered"
+        );
         key
     }
 
-    fn update(&mut self, key: usize, cx: &Context<'_>) -> usize {
-        if self.is_in_waiting_range(key) {
-            if let Some(w) = self.queue.iter_mut().find(|w| w.key == key) {
-                w.waker = cx.waker().clone();
-                return key;
+    /// Put a notified waiter back into the queue with its old key, in front of every waiter
+    /// with a newer key, for `WaitlistMode::FairRequeue`.
+    ///
+    /// Unless the queue is `unordered`, it stays sorted by key, oldest first, so the front
+    /// waiter still has the oldest key, and is still the one `min_key` is set from.
+    fn requeue(&mut self, key: usize, waker: Waker) -> usize {
+        self.stats.total_inserted += 1;
+        let next_key = self.next_key;
+        let age = next_key.wrapping_sub(key);
+        self.queue.insert_before(self.new_waiter(key, waker), |w| {
+            next_key.wrapping_sub(w.key) < age
+        });
+        self.recompute_min_key();
+        key
+    }
+
+    /// Add a waiter with a new key that will be woken before the waiters already queued.
+    fn insert_front(&mut self, waker: Waker) -> usize {
+        if self.mode == WaitlistMode::Lifo {
+            return self.insert_waker(waker);
+        }
+        self.stats.total_inserted += 1;
+        let key = self.take_key();
+        if !self.queue.is_empty() {
+            self.unordered = true;
+        }
+        self.queue.push_front(self.new_waiter(key, waker));
+        key
+    }
+
+    /// Update the waker for `key`, and move it to where it will be woken next, if it is
+    /// still waiting. Returns false if it isn't.
+    fn move_to_front(&mut self, key: usize, waker: &Waker) -> bool {
+        if !self.is_in_waiting_range(key) {
+            return false;
+        }
+        let Some(mut waiter) = self.queue.remove(key) else {
+            return false;
+        };
+        if !waiter.waker.will_wake(waker) {
+            waiter.waker = waker.clone();
+        }
+        #[cfg(feature = "timing")]
+        {
+            waiter.refreshed = self.clock.now();
+        }
+        let behind_others = !self.queue.is_empty();
+        if self.mode == WaitlistMode::Lifo {
+            self.queue.push_back(waiter);
+        } else {
+            self.queue.push_front(waiter);
+        }
+        // the waiter's key is still in the waiting range, since `min_key` hasn't changed,
+        // but it may now be in front of older keys
+        self.unordered |= behind_others;
+        true
+    }
+
+    /// Return true if the queue has reached its limit.
+    fn is_full(&self) -> bool {
+        matches!(self.limit, Some(limit) if self.queue.len() >= limit)
+    }
+
+    /// Like `insert`, but fail if the queue has reached its limit.
+    fn try_insert(&mut self, waker: Cow<'_, Waker>) -> Result<usize, WaitlistFull> {
+        if self.is_full() {
+            Err(WaitlistFull)
+        } else {
+            Ok(self.insert_waker(waker.into_owned()))
+        }
+    }
+
+    fn update(&mut self, key: usize, waker: Cow<'_, Waker>) -> usize {
+        match self.replace_if_pending(key, waker) {
+            Ok(()) => key,
+            Err((waker, true)) if self.mode == WaitlistMode::FairRequeue => {
+                self.requeue(key, waker.into_owned())
             }
+            Err((waker, _)) => self.insert_waker(waker.into_owned()),
+        }
+    }
+
+    /// Like `update`, but fail if a new entry is needed and the queue has reached its limit.
+    ///
+    /// On failure the task is no longer counted as notified.
+    fn try_update(&mut self, key: usize, waker: Cow<'_, Waker>) -> Result<usize, WaitlistFull> {
+        if !self.is_full() {
+            Ok(self.update(key, waker))
+        } else {
+            self.replace_if_pending(key, waker)
+                .map(|()| key)
+                .map_err(|_| WaitlistFull)
         }
-        self.notified_count -= 1; // the waiter was already notified, so we need to decrement the number of actively notified tasks
-        self.insert(cx)
     }
 
     fn remove(&mut self, key: usize) -> bool {
-        if self.is_in_waiting_range(key) {
-            if let Some(idx) = self.queue.iter().position(|w| w.key == key) {
-                self.queue.remove(idx);
-                return false;
+        if self.is_in_waiting_range(key) && self.queue.remove(key).is_some() {
+            self.recompute_min_key();
+            return false;
+        }
+        if self.take_cancelled(key) {
+            return false;
+        }
+        self.notified_handled()
+    }
+
+    /// Return true if the waiter for `key` was removed by a `CancelToken`, forgetting that
+    /// it was.
+    fn take_cancelled(&mut self, key: usize) -> bool {
+        !self.cancelled.is_empty() && self.cancelled.remove(&key)
+    }
+
+    /// Remove every waiter from the queue, remembering their keys as cancelled.
+    fn clear(&mut self) {
+        for waiter in self.queue.iter() {
+            self.cancelled.insert(waiter.key);
+        }
+        self.queue.clear();
+        self.recompute_min_key();
+    }
+
+    /// Reset `min_key` to the key of the oldest waiter still in the queue.
+    ///
+    /// This must be called after removing waiters from anywhere other than by popping the
+    /// front, so that `min_key` stays equal to the front key. If the queue is empty, all
+    /// existing keys are outside of the waiting range. While the queue is `unordered`,
+    /// `min_key` is left alone instead, since it is still below every key in the queue.
+    fn recompute_min_key(&mut self) {
+        match self.queue.front() {
+            None => {
+                self.min_key = self.next_key;
+                self.unordered = false;
             }
+            Some(_) if self.unordered => {}
+            Some(w) => self.min_key = w.key,
         }
-        self.notified_count -= 1;
-        true
     }
 
     fn cancel(&mut self, key: usize) -> bool {
+        self.stats.total_cancelled += 1;
         if self.remove(key) {
-            self.notify_first()
+            // pass on the notification, along with its payload
+            match self.take_payload(key) {
+                Some(payload) => self.notify_first_with(payload).is_ok(),
+                None => self.notify_first(),
+            }
         } else {
             false
         }
     }
 
+    /// Remove the waiter for `key`, like `remove`, then wake the next waiter whether or
+    /// not it had been notified.
+    fn remove_and_notify_next(&mut self, key: usize) -> bool {
+        self.remove(key);
+        self.notify_first()
+    }
+
+    fn take_payload(&mut self, key: usize) -> Option<T> {
+        if self.payloads.is_empty() {
+            None
+        } else {
+            self.payloads.remove(&key)
+        }
+    }
+
+    /// Notify the first waiter with `payload`, or give it back if the queue is empty.
+    fn notify_first_with(&mut self, payload: T) -> Result<(), T> {
+        match self.pop_first() {
+            Some(waiter) => {
+                self.payloads.insert(waiter.key, payload);
+                self.to_wake.push(waiter.waker);
+                Ok(())
+            }
+            None => Err(payload),
+        }
+    }
+
     /// Update the waker for the task for `key`, but only if it is still waiting to
     /// be woken.
     ///
     /// Return true if a waker was updated, false, if no waiting task was found.
     ///
-    /// If no waker was updated decrement the notified_count to mark that one of the notified tasks
+    /// If no waker was updated, and the waiter wasn't cancelled by a `CancelToken`,
+    /// decrement the notified_count to mark that one of the notified tasks
     /// has been handled.
     fn update_if_pending(&mut self, key: usize, cx: &Context<'_>) -> bool {
+        self.replace_if_pending(key, Cow::Borrowed(cx.waker()))
+            .is_ok()
+    }
+
+    /// Like `update_if_pending`, but give `waker` back if no waiting task was found, so
+    /// that it can be inserted again without another clone, along with whether the task
+    /// had been notified.
+    fn replace_if_pending<'w>(
+        &mut self,
+        key: usize,
+        waker: Cow<'w, Waker>,
+    ) -> Result<(), (Cow<'w, Waker>, bool)> {
         // all we really need to do here is decrement notified_count if the key isn't in the queue
         if self.is_in_waiting_range(key) {
-            if let Some(w) = self.queue.iter_mut().find(|w| w.key == key) {
-                w.waker = cx.waker().clone();
-                return true;
+            #[cfg(feature = "timing")]
+            let now = self.clock.now();
+            if let Some(w) = self.queue.get_mut(key) {
+                // runtimes usually poll with the same waker every time, so this saves
+                // cloning, and dropping the old one
+                if !w.waker.will_wake(&waker) {
+                    w.waker = waker.into_owned();
+                }
+                #[cfg(feature = "timing")]
+                {
+                    w.refreshed = now;
+                }
+                return Ok(());
             }
         }
+        let notified = !self.take_cancelled(key) && self.notified_handled();
+        Err((waker, notified))
+    }
+
+    /// Send a message to every subscribed channel, and remove any that are closed.
+    #[cfg(feature = "channel")]
+    fn notify_subscribers(&mut self) {
+        self.subscribers.retain(|tx| tx.unbounded_send(()).is_ok());
+    }
+
+    #[cfg(not(feature = "channel"))]
+    fn notify_subscribers(&mut self) {}
+
+    /// Mark that one of the notified tasks has been handled, and wake any tasks waiting
+    /// for all notified tasks to be handled.
+    ///
+    /// Returns false, without changing anything, if no notified tasks are outstanding. That
+    /// can only happen if the same wait was completed twice, for example by using a key
+    /// with `WaitHandle::from_key` after its handle was already finished, so the stale
+    /// completion is ignored rather than underflowing the count.
+    fn notified_handled(&mut self) -> bool {
+        if self.notified_count == 0 {
+            return false;
+        }
         self.notified_count -= 1;
-        false
+        if self.notified_count == 0 {
+            self.wake_drain_wakers();
+        }
+        true
+    }
+
+    fn wake_drain_wakers(&mut self) {
+        for waker in self.drain_wakers.drain(..) {
+            self.to_wake.push(waker);
+        }
     }
 
     fn notify_first(&mut self) -> bool {
-        if let Some(waiter) = self.queue.pop_front() {
-            self.notified_count += 1;
-            debug_assert!(waiter.key >= self.min_key);
-            self.min_key = waiter.key.wrapping_add(1);
-            waiter.waker.wake();
+        if let Some(waiter) = self.pop_first() {
+            self.to_wake.push(waiter.waker);
             true
         } else {
             false
         }
     }
 
-    fn notify_all(&mut self) -> bool {
+    /// Wake the next waiter, according to the mode, but leave it in the queue.
+    fn wake_first_by_ref(&mut self) -> bool {
+        let first = self.wake_order().next().map(|w| w.waker.clone());
+        match first {
+            Some(waker) => {
+                self.to_wake.push(waker);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Remove the next waiter to wake, according to the mode, and mark it as notified, but
+    /// return it instead of waking it.
+    fn pop_first(&mut self) -> Option<Waiter> {
+        let waiter = match self.mode {
+            WaitlistMode::Fifo | WaitlistMode::FairRequeue => {
+                let waiter = self.queue.pop_front()?;
+                debug_assert!(self.is_in_waiting_range(waiter.key));
+                if self.unordered {
+                    self.recompute_min_key();
+                } else {
+                    self.min_key = waiter.key.wrapping_add(1);
+                }
+                waiter
+            }
+            WaitlistMode::Lifo => {
+                let waiter = self.queue.pop_back()?;
+                self.recompute_min_key();
+                waiter
+            }
+        };
+        self.notified_count += 1;
+        self.stats.total_woken += 1;
+        Some(waiter)
+    }
+
+    /// Iterate over the waiters in the order they would be woken.
+    fn wake_order(&self) -> impl Iterator<Item = &Waiter> {
+        let (fifo, lifo) = match self.mode {
+            WaitlistMode::Fifo | WaitlistMode::FairRequeue => (Some(self.queue.iter()), None),
+            WaitlistMode::Lifo => (None, Some(self.queue.iter_rev())),
+        };
+        fifo.into_iter().flatten().chain(lifo.into_iter().flatten())
+    }
+
+    /// Wake the waiter for `key`, if it is still in the queue, leaving the others in order.
+    fn notify_key(&mut self, key: usize) -> bool {
+        if !self.is_in_waiting_range(key) {
+            return false;
+        }
+        let Some(waiter) = self.queue.remove(key) else {
+            return false;
+        };
+        self.notified_count += 1;
+        self.stats.total_woken += 1;
+        self.recompute_min_key();
+        self.to_wake.push(waiter.waker);
+        true
+    }
+
+    /// Wake up to `n` waiters from the front of the queue, passing the key of each one to
+    /// `on_key` before it is woken. Returns the number of waiters woken.
+    fn notify_n(&mut self, n: usize, mut on_key: impl FnMut(usize)) -> usize {
+        let mut woken = 0;
+        while woken < n {
+            let Some(waiter) = self.pop_first() else {
+                break;
+            };
+            on_key(waiter.key);
+            self.to_wake.push(waiter.waker);
+            woken += 1;
+        }
+        woken
+    }
+
+    fn notify_where(&mut self, pred: impl Fn(u64) -> bool) -> usize {
+        let woken = self.queue.extract(|w| pred(w.token));
+        let count = woken.len();
+        self.notified_count += count;
+        self.stats.total_woken += count;
+        // the waiters were taken from anywhere in the queue, maybe including the front
+        self.recompute_min_key();
+        if self.mode == WaitlistMode::Lifo {
+            for waiter in woken.into_iter().rev() {
+                self.to_wake.push(waiter.waker);
+            }
+        } else {
+            for waiter in woken {
+                self.to_wake.push(waiter.waker);
+            }
+        }
+        count
+    }
+
+    fn notify_since(&mut self, marker: usize) -> usize {
+        // keys in the range `marker..next_key`, allowing for wraparound
+        let since = self.next_key.wrapping_sub(marker);
+        let is_new = |w: &Waiter| w.key.wrapping_sub(marker) < since;
+        // the new waiters are all at the back, unless `set_context_front` moved some
+        let woken = if self.unordered {
+            self.queue.extract(is_new)
+        } else {
+            self.queue.split_off(is_new)
+        };
+        let count = woken.len();
+        self.notified_count += count;
+        self.stats.total_woken += count;
+        self.recompute_min_key();
+        for waiter in woken {
+            self.to_wake.push(waiter.waker);
+        }
+        count
+    }
+
+    fn notify_all(&mut self) -> usize {
         let num_notified = self.queue.len();
-        while let Some(w) = self.queue.pop_front() {
-            w.waker.wake();
+        if num_notified == 0 {
+            return 0;
         }
+        // The wakers are only collected here, and woken once the waitlist is unlocked, so
+        // that other tasks aren't kept waiting for the lock while they run.
+        let to_wake = &mut self.to_wake;
+        to_wake.reserve(num_notified);
+        self.queue
+            .drain(self.mode == WaitlistMode::Lifo, |w| to_wake.push(w.waker));
         self.notified_count += num_notified;
-        self.min_key = self.next_key;
-        num_notified > 0
+        self.stats.total_woken += num_notified;
+        self.recompute_min_key();
+        num_notified
+    }
+}
+
+/// Wakers collected while the waitlist is locked, which are woken when this is dropped.
+///
+/// The first waker is kept apart from the rest, so that notifying a single task doesn't
+/// allocate.
+#[derive(Default)]
+struct Wakers {
+    first: Option<Waker>,
+    rest: Vec<Waker>,
+}
+
+impl Wakers {
+    fn push(&mut self, waker: Waker) {
+        if self.first.is_none() {
+            self.first = Some(waker);
+        } else {
+            self.rest.push(waker);
+        }
+    }
+
+    fn reserve(&mut self, additional: usize) {
+        self.rest.reserve(additional.saturating_sub(1));
+    }
+
+    fn is_empty(&self) -> bool {
+        self.first.is_none()
+    }
+}
+
+impl Drop for Wakers {
+    fn drop(&mut self) {
+        let first = self.first.take();
+        let mut wakers = WakeRemaining(first.into_iter().chain(mem::take(&mut self.rest)));
+        for waker in wakers.0.by_ref() {
+            waker.wake();
+        }
+    }
+}
+
+/// Wakes the rest of the wakers if one of them panics, so that tasks that were already
+/// counted as notified aren't left waiting for a wake that never comes.
+struct WakeRemaining<I: Iterator<Item = Waker>>(I);
+
+impl<I: Iterator<Item = Waker>> Drop for WakeRemaining<I> {
+    fn drop(&mut self) {
+        for waker in &mut self.0 {
+            waker.wake();
+        }
     }
 }
 
-struct Guard<'a> {
+struct Guard<'a, T> {
     flags: &'a AtomicUsize,
-    inner: MutexGuard<'a, Inner>,
+    counters: &'a StatCounters,
+    inner: MutexGuard<'a, Inner<T>>,
+    /// Taken from `inner` when the guard is dropped. Fields are dropped in order, so these
+    /// are only woken after `inner` has unlocked the mutex, and a waker can notify the
+    /// same waitlist without deadlocking.
+    to_wake: Wakers,
 }
 
-impl<'a> Deref for Guard<'a> {
-    type Target = Inner;
+impl<T> Deref for Guard<'_, T> {
+    type Target = Inner<T>;
 
     #[inline]
-    fn deref(&self) -> &Inner {
+    fn deref(&self) -> &Inner<T> {
         &self.inner
     }
 }
 
-impl<'a> DerefMut for Guard<'a> {
+impl<T> DerefMut for Guard<'_, T> {
     #[inline]
-    fn deref_mut(&mut self) -> &mut Inner {
+    fn deref_mut(&mut self) -> &mut Inner<T> {
         &mut self.inner
     }
 }
 
-impl<'a> Drop for Guard<'a> {
+impl<T> Drop for Guard<'_, T> {
     fn drop(&mut self) {
+        // This also runs when unwinding from a panicking callback, like the predicate
+        // passed to `notify_while`. The flags are always computed from the current state,
+        // rather than tracked incrementally, so they stay correct however far the
+        // operation got.
         let mut flags = 0;
 
         if !self.queue.is_empty() {
@@ -372,26 +2538,194 @@ impl<'a> Drop for Guard<'a> {
             flags |= NOTIFIED;
         }
 
-        // Update flags. Use relaxed ordering because
-        // releasing the mutex will create a memory boundary.
+        #[cfg(feature = "channel")]
+        if !self.subscribers.is_empty() {
+            flags |= SUBSCRIBED;
+        }
+
+        // Update flags. The fence pairs with the one in `Waitlist::flags`, so that a task
+        // that registers and then checks its condition either sees the condition or is
+        // seen by the notifier, see "Memory ordering" in the docs for `Waitlist`.
         self.flags.store(flags, Ordering::Relaxed);
+        fence(Ordering::SeqCst);
+
+        let stats = mem::take(&mut self.inner.stats);
+        for (counter, delta) in [
+            (&self.counters.woken, stats.total_woken),
+            (&self.counters.inserted, stats.total_inserted),
+            (&self.counters.cancelled, stats.total_cancelled),
+            (&self.counters.woken_total, stats.total_woken),
+            (&self.counters.inserted_total, stats.total_inserted),
+        ] {
+            if delta > 0 {
+                counter.fetch_add(delta, Ordering::Relaxed);
+            }
+        }
+
+        if !self.inner.to_wake.is_empty() {
+            mem::swap(&mut self.to_wake, &mut self.inner.to_wake);
+        }
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use alloc::vec;
     use futures_task::noop_waker;
+    use std::cell::Cell;
+
+    std::thread_local! {
+        /// The number of times a waitlist has been locked on this thread
+        pub(super) static LOCK_COUNT: Cell<usize> = const { Cell::new(0) };
+    }
+
+    fn lock_count() -> usize {
+        LOCK_COUNT.with(Cell::get)
+    }
 
     #[test]
-    fn wraparound() {
-        const KEY_START: usize = usize::max_value() - 1;
-        let mut inner = Inner {
-            queue: VecDeque::new(),
-            notified_count: 0,
-            min_key: KEY_START,
-            next_key: KEY_START,
+    fn empty_notify_doesnt_lock() {
+        let waitlist = Waitlist::new();
+        let before = lock_count();
+        assert!(!waitlist.notify_one());
+        assert!(!waitlist.notify_all());
+        assert!(!waitlist.notify_any());
+        assert_eq!(0, waitlist.notify_adaptive());
+        assert!(!waitlist.notify_one_via(&drop));
+        assert_eq!(0, waitlist.notify_n(3));
+        assert_eq!(0, waitlist.notify_any_n(3));
+        assert_eq!(Ok(false), waitlist.try_notify_one());
+        assert!(!waitlist.notify_one_by_ref());
+        assert!(waitlist.is_empty());
+        assert_eq!(0, waitlist.len());
+        assert_eq!(before, lock_count());
+
+        // the same is true once the queue has been emptied again
+        let waker = noop_waker();
+        let mut handle = waitlist.wait();
+        handle.set_context(&Context::from_waker(&waker));
+        assert!(!handle.finish());
+        let before = lock_count();
+        assert!(!waitlist.notify_all());
+        assert_eq!(before, lock_count());
+    }
+
+    #[test]
+    fn try_notify_one_doesnt_wait_for_lock() {
+        let waitlist = Waitlist::new();
+        let waker = noop_waker();
+        let mut handle = waitlist.wait();
+        handle.set_context(&Context::from_waker(&waker));
+
+        let inner = waitlist.lock();
+        assert_eq!(Err(WouldBlock), waitlist.try_notify_one());
+        drop(inner);
+        assert_eq!(1, waitlist.len());
+        assert_eq!(Ok(true), waitlist.try_notify_one());
+        assert!(handle.finish());
+    }
+
+    #[test]
+    fn wait_many_locks_once() {
+        let waitlist = Waitlist::new();
+        {
+            let mut inner = waitlist.lock();
+            inner.min_key = usize::MAX - 1;
+            inner.next_key = usize::MAX - 1;
+        }
+        let waker = noop_waker();
+        let cx = Context::from_waker(&waker);
+        let before = lock_count();
+        let handles = waitlist.wait_many([&cx, &cx, &cx, &cx]);
+        assert_eq!(before + 1, lock_count());
+
+        // keys are consecutive, even across the wraparound
+        let keys: Vec<_> = handles
+            .iter()
+            .map(|h| h.key().unwrap().into_raw())
+            .collect();
+        assert_eq!(vec![usize::MAX - 1, usize::MAX, 0, 1], keys);
+        assert_eq!(2, waitlist.lock().next_key);
+        assert_eq!(4, waitlist.len());
+    }
+
+    #[test]
+    fn cancel_all_resets_state() {
+        let waitlist = Waitlist::new();
+        let waker = noop_waker();
+        let cx = Context::from_waker(&waker);
+        let mut handles = waitlist.wait_many([&cx, &cx, &cx]);
+        assert!(waitlist.notify_one_with(()).is_ok());
+
+        waitlist.cancel_all();
+        {
+            let inner = waitlist.lock();
+            assert_eq!(inner.next_key, inner.min_key);
+            assert_eq!(0, inner.notified_count);
+            assert!(inner.queue.is_empty());
+            assert!(inner.payloads.is_empty());
+        }
+        assert_eq!(0, waitlist.flags());
+
+        // none of the old handles count as handling a notification, or pass one on
+        assert!(!handles[0].cancel());
+        assert!(!handles[1].finish());
+        assert!(!handles[2].cancel());
+        assert_eq!(0, waitlist.lock().notified_count);
+        assert!(waitlist.lock().cancelled.is_empty());
+    }
+
+    #[test]
+    fn notify_all_keys_matches_notify_all() {
+        let waker = noop_waker();
+        let cx = Context::from_waker(&waker);
+        let state = |waitlist: &Waitlist| {
+            let inner = waitlist.lock();
+            (inner.min_key, inner.next_key, inner.notified_count)
         };
+        let (a, b) = (Waitlist::new(), Waitlist::new());
+        let _handles = (a.wait_many([&cx, &cx, &cx]), b.wait_many([&cx, &cx, &cx]));
+        assert!(a.notify_one());
+        assert!(b.notify_one());
+
+        assert!(a.notify_all());
+        assert_eq!(2, b.notify_all_keys().len());
+        assert_eq!(state(&a), state(&b));
+    }
+
+    #[test]
+    fn front_insertion_keeps_older_keys_in_range() {
+        let waitlist = Waitlist::new();
+        let waker = noop_waker();
+        let cx = Context::from_waker(&waker);
+        let handles = waitlist.wait_many([&cx, &cx]);
+        let mut front = waitlist.wait();
+        front.set_context_front(&cx);
+
+        // waking the front waiter, with the newest key, mustn't move the range past the
+        // older ones
+        assert_eq!(front.key(), waitlist.peek());
+        assert!(waitlist.notify_one());
+        for handle in &handles {
+            let key = handle.key().unwrap();
+            assert!(waitlist.lock().is_in_waiting_range(key.into_raw()));
+            assert!(waitlist.contains(key));
+        }
+        assert!(front.finish());
+        assert!(waitlist.lock().unordered);
+
+        // once the queue is empty, the range and ordering are exact again
+        assert_eq!(2, waitlist.notify_all_count());
+        let inner = waitlist.lock();
+        assert!(!inner.unordered);
+        assert_eq!(inner.next_key, inner.min_key);
+    }
+
+    #[test]
+    fn wraparound() {
+        const KEY_START: usize = usize::MAX - 1;
+        let mut inner = inner_starting_at(KEY_START);
 
         let waker = noop_waker();
         let context = Context::from_waker(&waker);
@@ -402,11 +2736,98 @@ mod test {
         assert_eq!(0, k3);
         assert_eq!(1, inner.next_key);
         assert!(inner.notify_first());
-        assert_eq!(usize::max_value(), inner.min_key);
+        assert_eq!(usize::MAX, inner.min_key);
         assert!(inner.is_in_waiting_range(k2));
         assert!(inner.is_in_waiting_range(k3));
-        assert_eq!(0, inner.update(0, &context));
+        assert_eq!(0, inner.update(0, Cow::Borrowed(context.waker())));
         assert!(!inner.remove(0));
         assert!(!inner.remove(k2));
     }
+
+    fn inner_starting_at(key: usize) -> Inner<()> {
+        let mut inner = Inner::new(0, None, WaitlistMode::Fifo);
+        inner.min_key = key;
+        inner.next_key = key;
+        inner
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    #[should_panic(expected = "a task stayed parked while usize::MAX other waiters registered")]
+    fn key_reuse_is_caught() {
+        let waker = noop_waker();
+        let context = Context::from_waker(&waker);
+        let mut inner = inner_starting_at(0);
+        let parked = inner.insert(&context);
+        // every other key has been handed out since, and the next insertion would wrap
+        // `next_key` around to the parked waiter's key
+        inner.next_key = parked.wrapping_sub(1);
+        inner.insert(&context);
+    }
+
+    #[test]
+    fn interior_removal_keeps_range() {
+        let waker = noop_waker();
+        let context = Context::from_waker(&waker);
+        let mut inner = inner_starting_at(10);
+        let keys: Vec<usize> = (0..5).map(|_| inner.insert(&context)).collect();
+
+        assert!(!inner.remove(keys[2]));
+        assert_eq!(keys[0], inner.min_key);
+        for &k in &[keys[0], keys[1], keys[3], keys[4]] {
+            assert!(inner.is_in_waiting_range(k));
+        }
+
+        assert!(!inner.remove(keys[0]));
+        assert!(!inner.remove(keys[1]));
+        assert_eq!(keys[3], inner.min_key);
+        assert!(!inner.is_in_waiting_range(keys[0]));
+        assert!(!inner.is_in_waiting_range(keys[1]));
+        assert!(!inner.is_in_waiting_range(keys[2]));
+        assert!(inner.is_in_waiting_range(keys[3]));
+        assert!(inner.is_in_waiting_range(keys[4]));
+
+        inner.remove(keys[3]);
+        inner.remove(keys[4]);
+        assert_eq!(inner.next_key, inner.min_key);
+        assert!(keys.iter().all(|&k| !inner.is_in_waiting_range(k)));
+    }
+
+    #[test]
+    fn notify_since_wraparound() {
+        let waker = noop_waker();
+        let context = Context::from_waker(&waker);
+        let mut inner = inner_starting_at(usize::MAX - 1);
+        inner.insert(&context);
+        let marker = inner.next_key;
+        let keys: Vec<usize> = (0..2).map(|_| inner.insert(&context)).collect();
+        assert_eq!(vec![usize::MAX, 0], keys);
+
+        assert_eq!(2, inner.notify_since(marker));
+        assert_eq!(1, inner.queue.len());
+        assert_eq!(usize::MAX - 1, inner.min_key);
+        assert_eq!(0, inner.notify_since(marker));
+        assert_eq!(2, inner.notified_count);
+    }
+
+    #[test]
+    fn interior_removal_wraparound() {
+        let waker = noop_waker();
+        let context = Context::from_waker(&waker);
+        let mut inner = inner_starting_at(usize::MAX - 1);
+        let keys: Vec<usize> = (0..4).map(|_| inner.insert(&context)).collect();
+        assert_eq!(vec![usize::MAX - 1, usize::MAX, 0, 1], keys);
+
+        assert!(!inner.remove(usize::MAX));
+        assert!(!inner.remove(usize::MAX - 1));
+        assert_eq!(0, inner.min_key);
+        assert!(!inner.is_in_waiting_range(usize::MAX - 1));
+        assert!(!inner.is_in_waiting_range(usize::MAX));
+        assert!(inner.is_in_waiting_range(0));
+        assert!(inner.is_in_waiting_range(1));
+
+        // the front is still consistent with `min_key` for notify_first's assertion
+        assert!(inner.notify_first());
+        assert_eq!(1, inner.min_key);
+    }
 }
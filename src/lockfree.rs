@@ -0,0 +1,216 @@
+//! An experimental waitlist that doesn't lock, for comparing against [`Waitlist`].
+//!
+//! [`LockFreeWaitlist`] keeps its waiters in a lock-free MPMC queue from `crossbeam-queue`
+//! instead of a queue behind a mutex. Each waiter is a slot with an atomic state and an
+//! [`AtomicWaker`], so registering, notifying and finishing a wait never take a lock.
+//!
+//! It is a prototype for measuring whether lock-freedom is worth it for this crate, and
+//! only supports the basic operations: registering a task, [`LockFreeWaitlist::notify_one`],
+//! [`LockFreeWaitlist::notify_all`], and finishing or cancelling a wait. A lock-free queue
+//! can only push to the back and pop from the front, so nothing that needs to find a waiter
+//! by key, like [`Waitlist::notify_key`] or payloads, can be built on it directly. A
+//! finished or cancelled waiter is only marked as done, and stays in the queue until a
+//! notification reaches it and skips it, so a waitlist that is rarely notified keeps
+//! growing.
+//!
+//! `benches/lockfree.rs` compares it with [`Waitlist`] when many threads register and
+//! notify at once. On a single core, it took about 30% less time per wait when every thread
+//! both registers and notifies, and about the same time when some threads only register
+//! and others only notify. It hasn't been measured on several cores, where lock contention
+//! would matter more. Either way, the missing operations mean it can't replace the queue
+//! behind [`Waitlist`], so it stays a separate, experimental type.
+//!
+//! This module is only available with the `lockfree` feature.
+//!
+//! [`Waitlist`]: crate::Waitlist
+//! [`Waitlist::notify_key`]: crate::Waitlist::notify_key
+
+use alloc::sync::Arc;
+use core::fmt;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::task::Context;
+
+use atomic_waker::AtomicWaker;
+use crossbeam_queue::SegQueue;
+
+// States of a slot. A slot starts out waiting, and then changes state exactly once.
+const WAITING: usize = 0;
+const NOTIFIED: usize = 1;
+const DONE: usize = 2;
+
+/// A single registration of a task.
+struct Slot {
+    state: AtomicUsize,
+    waker: AtomicWaker,
+}
+
+impl Slot {
+    fn new(cx: &Context<'_>) -> Arc<Slot> {
+        let slot = Arc::new(Slot {
+            state: AtomicUsize::new(WAITING),
+            waker: AtomicWaker::new(),
+        });
+        slot.waker.register(cx.waker());
+        slot
+    }
+
+    /// Mark the slot as done if it is still waiting. Returns true if it had been notified
+    /// instead.
+    fn finish(&self) -> bool {
+        self.state
+            .compare_exchange(WAITING, DONE, Ordering::AcqRel, Ordering::Acquire)
+            .is_err()
+    }
+}
+
+/// A list of waiting tasks that is updated without locking.
+///
+/// See the [module level documentation](self) for details.
+pub struct LockFreeWaitlist {
+    queue: SegQueue<Arc<Slot>>,
+}
+
+/// Handle for controlling the wait status of a task on a [`LockFreeWaitlist`].
+///
+/// Dropping a handle that was notified passes the notification on to the next task.
+pub struct LockFreeWaitHandle<'a> {
+    waitlist: &'a LockFreeWaitlist,
+    slot: Option<Arc<Slot>>,
+}
+
+impl LockFreeWaitlist {
+    /// Create a new, empty `LockFreeWaitlist`.
+    pub fn new() -> LockFreeWaitlist {
+        LockFreeWaitlist {
+            queue: SegQueue::new(),
+        }
+    }
+
+    /// Return a handle a task can use to wait for events.
+    ///
+    /// Like [`Waitlist::wait`](crate::Waitlist::wait), this doesn't register the task
+    /// until [`LockFreeWaitHandle::set_context`] is called.
+    #[inline]
+    pub fn wait(&self) -> LockFreeWaitHandle<'_> {
+        LockFreeWaitHandle {
+            waitlist: self,
+            slot: None,
+        }
+    }
+
+    /// Wake the task that has waited longest.
+    ///
+    /// Returns true if a task was woken, and false if no task was waiting.
+    pub fn notify_one(&self) -> bool {
+        while let Some(slot) = self.queue.pop() {
+            let notified = slot
+                .state
+                .compare_exchange(WAITING, NOTIFIED, Ordering::AcqRel, Ordering::Acquire)
+                .is_ok();
+            if notified {
+                slot.waker.wake();
+                return true;
+            }
+            // the task finished its wait without being notified, so skip it
+        }
+        false
+    }
+
+    /// Wake every task waiting when this is called.
+    ///
+    /// Tasks that register while this runs, for example from their waker, may or may not
+    /// be woken. Returns the number of tasks woken.
+    pub fn notify_all(&self) -> usize {
+        let mut woken = 0;
+        // bound the loop by the queue's length, so that re-registering tasks can't keep
+        // it going forever
+        for _ in 0..self.queue.len() {
+            if !self.notify_one() {
+                break;
+            }
+            woken += 1;
+        }
+        woken
+    }
+}
+
+impl Default for LockFreeWaitlist {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for LockFreeWaitlist {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("LockFreeWaitlist")
+            .field("queued", &self.queue.len())
+            .finish()
+    }
+}
+
+impl LockFreeWaitHandle<'_> {
+    /// Register the waker from `cx` to be woken when this task is notified.
+    ///
+    /// If the task is still waiting, its waker is updated and it keeps its place. If it
+    /// was notified, it joins the back of the queue again.
+    pub fn set_context(&mut self, cx: &Context<'_>) {
+        if let Some(slot) = &self.slot {
+            if slot.state.load(Ordering::Acquire) == WAITING {
+                slot.waker.register(cx.waker());
+                // a notification between the check and registering may have woken the
+                // old waker instead
+                if slot.state.load(Ordering::Acquire) != WAITING {
+                    cx.waker().wake_by_ref();
+                }
+                return;
+            }
+        }
+        let slot = Slot::new(cx);
+        self.waitlist.queue.push(slot.clone());
+        self.slot = Some(slot);
+    }
+
+    /// Mark this task as completed.
+    ///
+    /// Returns true if the task had been notified. Otherwise it leaves the waitlist without
+    /// notifying another task, and this returns false.
+    pub fn finish(&mut self) -> bool {
+        match self.slot.take() {
+            Some(slot) => slot.finish(),
+            None => false,
+        }
+    }
+
+    /// Mark that the task was cancelled.
+    ///
+    /// If the task had been notified, the notification is passed on to the next task, and
+    /// this returns true if there was one.
+    pub fn cancel(&mut self) -> bool {
+        self.finish() && self.waitlist.notify_one()
+    }
+
+    /// Return true if the task has been notified since it last registered.
+    pub fn is_notified(&self) -> bool {
+        matches!(&self.slot, Some(slot) if slot.state.load(Ordering::Acquire) == NOTIFIED)
+    }
+
+    /// Return true if the handle has been registered, and has not been completed with
+    /// `finish` or `cancel`.
+    pub fn is_pending(&self) -> bool {
+        self.slot.is_some()
+    }
+}
+
+impl Drop for LockFreeWaitHandle<'_> {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
+
+impl fmt::Debug for LockFreeWaitHandle<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("LockFreeWaitHandle")
+            .field("pending", &self.is_pending())
+            .finish()
+    }
+}
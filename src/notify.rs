@@ -0,0 +1,158 @@
+//! A [`Notify`] primitive with the same shape as `tokio::sync::Notify`.
+//!
+//! # Differences from tokio
+//!
+//! [`Notify`] aims to behave the same as tokio's `Notify`, so that it can be swapped in
+//! without changing the code using it:
+//!
+//!  * [`Notify::notify_one`] wakes the oldest waiting [`Notified`] future. If nothing is
+//!    waiting, a single permit is stored and the next [`Notified`] future completes
+//!    immediately. Calling it several times while nothing is waiting still only stores
+//!    one permit.
+//!  * [`Notify::notify_waiters`] wakes every [`Notified`] future created before the call,
+//!    whether or not it has been polled, and never stores a permit.
+//!  * Dropping a [`Notified`] future that was woken by `notify_one` but hasn't completed
+//!    passes the notification on to the next waiter, or stores it as the permit.
+//!
+//! It intentionally differs in a few ways:
+//!
+//!  * There is no `Notified::enable`. A future only joins the queue of waiters the
+//!    first time it is polled.
+//!  * [`Notified`] is `Unpin`.
+//!  * There is no `notify_last`.
+
+use core::fmt;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use core::task::{Context, Poll};
+
+use crate::Waitlist;
+
+/// Notify a task to wake up, with an API compatible with `tokio::sync::Notify`.
+///
+/// See the [module level documentation](self) for details on the semantics.
+pub struct Notify {
+    waitlist: Waitlist,
+    /// Whether a permit is stored. Only modified while `waitlist` is locked.
+    permit: AtomicBool,
+    /// The number of times `notify_waiters` has been called. Only modified while
+    /// `waitlist` is locked.
+    generation: AtomicUsize,
+}
+
+/// Future returned by [`Notify::notified`].
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct Notified<'a> {
+    notify: &'a Notify,
+    key: Option<usize>,
+    generation: usize,
+    done: bool,
+}
+
+impl Notify {
+    /// Create a new `Notify` without a stored permit.
+    pub fn new() -> Notify {
+        Notify {
+            waitlist: Waitlist::new(),
+            permit: AtomicBool::new(false),
+            generation: AtomicUsize::new(0),
+        }
+    }
+
+    /// Wake the oldest waiting task, or store a permit if no task is waiting.
+    pub fn notify_one(&self) {
+        let mut inner = self.waitlist.lock();
+        if !inner.notify_first() {
+            self.permit.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// Wake all tasks waiting on a [`Notified`] future created before this call.
+    pub fn notify_waiters(&self) {
+        let mut inner = self.waitlist.lock();
+        self.generation.fetch_add(1, Ordering::Release);
+        inner.notify_all();
+    }
+
+    /// Wait for a notification.
+    pub fn notified(&self) -> Notified<'_> {
+        Notified {
+            notify: self,
+            key: None,
+            generation: self.generation.load(Ordering::Acquire),
+            done: false,
+        }
+    }
+}
+
+impl Default for Notify {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for Notify {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Notify")
+            .field("permit", &self.permit.load(Ordering::Relaxed))
+            .finish()
+    }
+}
+
+impl Future for Notified<'_> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.done {
+            return Poll::Ready(());
+        }
+        let notify = self.notify;
+        let mut inner = notify.waitlist.lock();
+        if notify.generation.load(Ordering::Relaxed) != self.generation {
+            if let Some(key) = self.key.take() {
+                inner.remove(key);
+            }
+            self.done = true;
+        } else if let Some(key) = self.key {
+            if !inner.update_if_pending(key, cx) {
+                self.key = None;
+                self.done = true;
+            }
+        } else if notify.permit.swap(false, Ordering::Relaxed) {
+            self.done = true;
+        } else {
+            self.key = Some(inner.insert(cx));
+        }
+
+        if self.done {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+impl fmt::Debug for Notified<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Notified")
+            .field("key", &self.key)
+            .field("done", &self.done)
+            .finish()
+    }
+}
+
+impl Drop for Notified<'_> {
+    fn drop(&mut self) {
+        if let Some(key) = self.key {
+            let mut inner = self.notify.waitlist.lock();
+            // If we were notified by `notify_one`, pass the notification on
+            if inner.remove(key)
+                && self.notify.generation.load(Ordering::Relaxed) == self.generation
+                && !inner.notify_first()
+            {
+                self.notify.permit.store(true, Ordering::Relaxed);
+            }
+        }
+    }
+}
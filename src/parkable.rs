@@ -0,0 +1,76 @@
+//! Object safe traits for things a task can wait on.
+//!
+//! [`Parkable`] lets code be written against `dyn Parkable` instead of a concrete waiting
+//! primitive, so that different primitives can be swapped in behind a trait object.
+
+use alloc::boxed::Box;
+use core::task::Context;
+
+use crate::{WaitHandle, Waitlist};
+
+/// Something a task can register itself on, and be notified by.
+pub trait Parkable {
+    /// Register the task for `cx` as waiting, returning a token to control the wait.
+    fn register<'a>(&'a self, cx: &Context<'_>) -> Box<dyn WaitToken + 'a>;
+
+    /// Wake one waiting task, returning true if a task was woken.
+    fn notify_one(&self) -> bool;
+}
+
+/// The registration of a single task on a [`Parkable`].
+///
+/// The wait is cancelled when the token is dropped.
+pub trait WaitToken {
+    /// Update the waker to notify, registering again if the task was already notified.
+    fn set_context(&mut self, cx: &Context<'_>);
+
+    /// Mark the wait as finished, returning true if the task had been notified.
+    fn finish(&mut self) -> bool;
+
+    /// Cancel the wait, passing on a notification to the next task if there is one.
+    ///
+    /// Returns true if another task was notified.
+    fn cancel(&mut self) -> bool;
+
+    /// Return true if the task is registered and hasn't finished or cancelled.
+    fn is_pending(&self) -> bool;
+
+    /// Mark as finished if the task was notified, otherwise update the waker.
+    ///
+    /// Returns true if the wait is finished.
+    fn try_finish(&mut self, cx: &mut Context<'_>) -> bool;
+}
+
+impl<T> Parkable for Waitlist<T> {
+    fn register<'a>(&'a self, cx: &Context<'_>) -> Box<dyn WaitToken + 'a> {
+        let mut handle = self.wait();
+        handle.set_context(cx);
+        Box::new(handle)
+    }
+
+    fn notify_one(&self) -> bool {
+        Waitlist::notify_one(self)
+    }
+}
+
+impl<T> WaitToken for WaitHandle<'_, T> {
+    fn set_context(&mut self, cx: &Context<'_>) {
+        WaitHandle::set_context(self, cx)
+    }
+
+    fn finish(&mut self) -> bool {
+        WaitHandle::finish(self)
+    }
+
+    fn cancel(&mut self) -> bool {
+        WaitHandle::cancel(self)
+    }
+
+    fn is_pending(&self) -> bool {
+        WaitHandle::is_pending(self)
+    }
+
+    fn try_finish(&mut self, cx: &mut Context<'_>) -> bool {
+        WaitHandle::try_finish(self, cx)
+    }
+}
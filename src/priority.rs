@@ -0,0 +1,374 @@
+//! A waitlist that wakes tasks by priority instead of arrival order.
+//!
+//! [`PriorityWaitlist`] works like [`Waitlist`](crate::Waitlist), except that each task
+//! gives a priority when it registers, and [`PriorityWaitlist::notify_one`] wakes the
+//! waiting task with the highest priority. Tasks with the same priority are woken in the
+//! order they started waiting.
+//!
+//! The waiters are kept in a binary heap, so registering and notifying a task take time
+//! logarithmic in the number of waiting tasks. A map from each waiter's key to its place in
+//! the heap lets a task cancel its wait, or change its priority, from anywhere in the heap.
+//!
+//! Like for [`Waitlist`](crate::Waitlist), notified tasks are only woken once the waitlist
+//! is unlocked, so a waker can use the waitlist that woke it.
+
+use alloc::vec::Vec;
+use core::fmt;
+use core::mem;
+use core::ops::{Deref, DerefMut};
+use core::task::{Context, Waker};
+
+use crate::sync::{Mutex, MutexGuard};
+use crate::{Map, Wakers};
+
+struct Entry {
+    priority: u64,
+    /// The order the waiter was added in, used to break ties between equal priorities
+    key: u64,
+    waker: Waker,
+}
+
+impl Entry {
+    /// Return true if this entry should be woken before `other`.
+    fn before(&self, other: &Entry) -> bool {
+        self.priority > other.priority || (self.priority == other.priority && self.key < other.key)
+    }
+}
+
+/// A binary max-heap of waiters, with the position of each key in the heap.
+struct Inner {
+    heap: Vec<Entry>,
+    positions: Map<u64, usize>,
+    notified_count: usize,
+    /// Keys are 64 bits even on smaller platforms, so that they never wrap around and can
+    /// always be compared to order equal priorities.
+    next_key: u64,
+    /// Wakers of notified tasks, woken once the waitlist is unlocked
+    to_wake: Wakers,
+}
+
+impl Inner {
+    fn insert(&mut self, cx: &Context<'_>, priority: u64) -> u64 {
+        let key = self.next_key;
+        self.next_key += 1;
+        let pos = self.heap.len();
+        self.heap.push(Entry {
+            priority,
+            key,
+            waker: cx.waker().clone(),
+        });
+        self.positions.insert(key, pos);
+        self.sift_up(pos);
+        key
+    }
+
+    /// Update the waker and priority for `key`, or add a new waiter if it was notified.
+    fn update(&mut self, key: u64, cx: &Context<'_>, priority: u64) -> u64 {
+        let Some(&pos) = self.positions.get(&key) else {
+            self.notified_handled();
+            return self.insert(cx, priority);
+        };
+        let entry = &mut self.heap[pos];
+        if !entry.waker.will_wake(cx.waker()) {
+            entry.waker = cx.waker().clone();
+        }
+        let old = mem::replace(&mut entry.priority, priority);
+        if priority > old {
+            self.sift_up(pos);
+        } else if priority < old {
+            self.sift_down(pos);
+        }
+        key
+    }
+
+    /// Remove the waiter for `key`. Returns true if it had already been notified instead.
+    fn remove(&mut self, key: u64) -> bool {
+        match self.positions.get(&key) {
+            Some(&pos) => {
+                self.remove_at(pos);
+                false
+            }
+            None => {
+                self.notified_handled();
+                true
+            }
+        }
+    }
+
+    fn cancel(&mut self, key: u64) -> bool {
+        if self.remove(key) {
+            // pass on the notification
+            self.notify_one()
+        } else {
+            false
+        }
+    }
+
+    fn notified_handled(&mut self) {
+        self.notified_count = self.notified_count.saturating_sub(1);
+    }
+
+    fn notify_one(&mut self) -> bool {
+        if self.heap.is_empty() {
+            return false;
+        }
+        let entry = self.remove_at(0);
+        self.notified_count += 1;
+        self.to_wake.push(entry.waker);
+        true
+    }
+
+    fn remove_at(&mut self, pos: usize) -> Entry {
+        let entry = self.heap.swap_remove(pos);
+        self.positions.remove(&entry.key);
+        if pos < self.heap.len() {
+            // the last entry was moved into the gap, and may belong above or below it
+            self.positions.insert(self.heap[pos].key, pos);
+            let pos = self.sift_up(pos);
+            self.sift_down(pos);
+        }
+        entry
+    }
+
+    /// Move the entry at `pos` up until its parent comes before it, and return where it
+    /// ends up.
+    fn sift_up(&mut self, mut pos: usize) -> usize {
+        while pos > 0 {
+            let parent = (pos - 1) / 2;
+            if !self.heap[pos].before(&self.heap[parent]) {
+                break;
+            }
+            self.swap(pos, parent);
+            pos = parent;
+        }
+        pos
+    }
+
+    /// Move the entry at `pos` down until it comes before both of its children.
+    fn sift_down(&mut self, mut pos: usize) {
+        loop {
+            let mut first = pos;
+            for child in [2 * pos + 1, 2 * pos + 2] {
+                if child < self.heap.len() && self.heap[child].before(&self.heap[first]) {
+                    first = child;
+                }
+            }
+            if first == pos {
+                break;
+            }
+            self.swap(pos, first);
+            pos = first;
+        }
+    }
+
+    fn swap(&mut self, a: usize, b: usize) {
+        self.heap.swap(a, b);
+        self.positions.insert(self.heap[a].key, a);
+        self.positions.insert(self.heap[b].key, b);
+    }
+}
+
+/// A list of waiting tasks, woken in order of priority.
+///
+/// See the [module level documentation](self) for details.
+pub struct PriorityWaitlist {
+    inner: Mutex<Inner>,
+}
+
+/// Handle for controlling the wait status of a task on a [`PriorityWaitlist`].
+///
+/// Dropping a pending handle cancels its wait.
+pub struct PriorityWaitHandle<'a> {
+    waitlist: &'a PriorityWaitlist,
+    key: Option<u64>,
+}
+
+impl PriorityWaitlist {
+    /// Create a new `PriorityWaitlist`
+    #[inline]
+    pub fn new() -> PriorityWaitlist {
+        Self::with_capacity(0)
+    }
+
+    /// Create a new priority waitlist with room for `cap` waiting tasks.
+    pub fn with_capacity(cap: usize) -> PriorityWaitlist {
+        PriorityWaitlist {
+            inner: Mutex::new(Inner {
+                heap: Vec::with_capacity(cap),
+                #[cfg(feature = "std")]
+                positions: Map::with_capacity(cap),
+                #[cfg(not(feature = "std"))]
+                positions: Map::new(),
+                notified_count: 0,
+                next_key: 0,
+                to_wake: Wakers::default(),
+            }),
+        }
+    }
+
+    fn lock(&self) -> Guard<'_> {
+        Guard {
+            inner: self.inner.lock(),
+            to_wake: Wakers::default(),
+        }
+    }
+
+    /// Return a handle a task can use to wait for events.
+    ///
+    /// Like [`Waitlist::wait`](crate::Waitlist::wait), this doesn't register the task
+    /// until [`PriorityWaitHandle::set_context`] is called.
+    #[inline]
+    pub fn wait(&self) -> PriorityWaitHandle<'_> {
+        PriorityWaitHandle {
+            waitlist: self,
+            key: None,
+        }
+    }
+
+    /// Return the number of tasks waiting to be notified.
+    pub fn len(&self) -> usize {
+        self.lock().heap.len()
+    }
+
+    /// Return true if no task is waiting to be notified.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Wake the waiting task with the highest priority, breaking ties by waking the one
+    /// that has waited longest.
+    ///
+    /// Returns true if a task was woken.
+    pub fn notify_one(&self) -> bool {
+        self.lock().notify_one()
+    }
+
+    /// Wake the highest priority task, but only if no other task has been notified and
+    /// not yet handled it.
+    ///
+    /// Returns true if a task was woken.
+    pub fn notify_any(&self) -> bool {
+        let mut inner = self.lock();
+        inner.notified_count == 0 && inner.notify_one()
+    }
+
+    /// Wake all waiting tasks, from the highest priority to the lowest.
+    ///
+    /// Returns the number of tasks woken.
+    pub fn notify_all(&self) -> usize {
+        let mut inner = self.lock();
+        let len = inner.heap.len();
+        inner.to_wake.reserve(len);
+        let mut woken = 0;
+        while inner.notify_one() {
+            woken += 1;
+        }
+        woken
+    }
+}
+
+impl Default for PriorityWaitlist {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for PriorityWaitlist {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PriorityWaitlist")
+            .field("len", &self.len())
+            .finish()
+    }
+}
+
+impl PriorityWaitHandle<'_> {
+    /// Register the waker from `cx` to be woken, ahead of any task with a lower
+    /// `priority`, when this task is notified.
+    ///
+    /// If the task is still waiting, its waker and priority are updated, and it keeps its
+    /// place among tasks of the same priority. If it was notified, it joins the waitlist
+    /// again behind the tasks already waiting with that priority.
+    pub fn set_context(&mut self, cx: &Context<'_>, priority: u64) {
+        let mut inner = self.waitlist.lock();
+        let key = match self.key {
+            Some(key) => inner.update(key, cx, priority),
+            None => inner.insert(cx, priority),
+        };
+        self.key = Some(key);
+    }
+
+    /// Mark this task as completed.
+    ///
+    /// Returns true if the task had been notified. Otherwise it is removed from the
+    /// waitlist without notifying another task, and this returns false.
+    pub fn finish(&mut self) -> bool {
+        match self.key.take() {
+            Some(key) => self.waitlist.lock().remove(key),
+            None => false,
+        }
+    }
+
+    /// Mark that the task was cancelled.
+    ///
+    /// If the task had been notified, the notification is passed on to the highest priority
+    /// task still waiting, and this returns true if there was one.
+    pub fn cancel(&mut self) -> bool {
+        match self.key.take() {
+            Some(key) => self.waitlist.lock().cancel(key),
+            None => false,
+        }
+    }
+
+    /// Return true if the handle has been registered, and has not been completed with
+    /// `finish` or `cancel`.
+    pub fn is_pending(&self) -> bool {
+        self.key.is_some()
+    }
+}
+
+impl Drop for PriorityWaitHandle<'_> {
+    fn drop(&mut self) {
+        if let Some(key) = self.key {
+            self.waitlist.lock().cancel(key);
+        }
+    }
+}
+
+impl fmt::Debug for PriorityWaitHandle<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PriorityWaitHandle")
+            .field("pending", &self.is_pending())
+            .finish()
+    }
+}
+
+struct Guard<'a> {
+    inner: MutexGuard<'a, Inner>,
+    /// Taken from `inner` when the guard is dropped, and woken after `inner` has unlocked
+    /// the mutex, like in `crate::Guard`.
+    to_wake: Wakers,
+}
+
+impl Deref for Guard<'_> {
+    type Target = Inner;
+
+    #[inline]
+    fn deref(&self) -> &Inner {
+        &self.inner
+    }
+}
+
+impl DerefMut for Guard<'_> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut Inner {
+        &mut self.inner
+    }
+}
+
+impl Drop for Guard<'_> {
+    fn drop(&mut self) {
+        if !self.inner.to_wake.is_empty() {
+            mem::swap(&mut self.to_wake, &mut self.inner.to_wake);
+        }
+    }
+}
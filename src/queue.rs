@@ -0,0 +1,392 @@
+//! The FIFO queue of waiters, with fast lookup and removal by key.
+//!
+//! Keys are found through a `Map`, which is a `HashMap` with the `std` feature, so lookup
+//! and removal take expected constant time, and a `BTreeMap` without it, where they take
+//! time logarithmic in the number of waiters. Unlinking the waiter once it is found is
+//! constant time either way.
+
+use alloc::vec::Vec;
+use core::mem;
+
+use crate::{Map, Waiter};
+
+/// A waiter in the slab, linked to its neighbours in the queue.
+struct Node {
+    waiter: Waiter,
+    prev: Option<usize>,
+    next: Option<usize>,
+}
+
+enum Slot {
+    Occupied(Node),
+    /// A free slot, and the next free slot after it
+    Vacant(Option<usize>),
+}
+
+/// Waiters in the order they were added, indexed by key.
+///
+/// The waiters are stored in a slab, and kept in order by a doubly linked list through the
+/// slab's slots, so that a waiter can be unlinked from anywhere in the queue without moving
+/// the others. Keys are mapped to the slot holding their waiter, so the keys handed out by
+/// the waitlist don't depend on where a waiter happens to be stored.
+pub(crate) struct WaiterQueue {
+    slots: Vec<Slot>,
+    /// The first free slot, if any
+    free: Option<usize>,
+    head: Option<usize>,
+    tail: Option<usize>,
+    index: Map<usize, usize>,
+}
+
+impl WaiterQueue {
+    pub(crate) fn with_capacity(cap: usize) -> WaiterQueue {
+        WaiterQueue {
+            slots: Vec::with_capacity(cap),
+            free: None,
+            head: None,
+            tail: None,
+            #[cfg(feature = "std")]
+            index: Map::with_capacity(cap),
+            #[cfg(not(feature = "std"))]
+            index: Map::new(),
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    pub(crate) fn capacity(&self) -> usize {
+        #[cfg(feature = "std")]
+        return self.slots.capacity().min(self.index.capacity());
+        #[cfg(not(feature = "std"))]
+        return self.slots.capacity();
+    }
+
+    pub(crate) fn reserve(&mut self, additional: usize) {
+        let needed = self.len().saturating_add(additional);
+        self.slots.reserve(needed.saturating_sub(self.slots.len()));
+        #[cfg(feature = "std")]
+        self.index.reserve(additional);
+    }
+
+    /// Move the waiters to the start of the slab, in order, and free the rest of it.
+    pub(crate) fn shrink_to_fit(&mut self) {
+        let mut waiters = Vec::with_capacity(self.len());
+        while let Some(waiter) = self.pop_front() {
+            waiters.push(waiter);
+        }
+        self.slots = Vec::with_capacity(waiters.len());
+        self.free = None;
+        #[cfg(feature = "std")]
+        self.index.shrink_to_fit();
+        for waiter in waiters {
+            self.push_back(waiter);
+        }
+    }
+
+    /// Add a waiter to the back of the queue. Its key must not already be in the queue.
+    pub(crate) fn push_back(&mut self, waiter: Waiter) {
+        self.link_before(waiter, None);
+    }
+
+    /// Add a waiter to the front of the queue. Its key must not already be in the queue.
+    pub(crate) fn push_front(&mut self, waiter: Waiter) {
+        self.link_before(waiter, self.head);
+    }
+
+    /// Add a waiter in front of the first waiter that matches `at`, or at the back if none
+    /// match. Its key must not already be in the queue.
+    pub(crate) fn insert_before(&mut self, waiter: Waiter, at: impl Fn(&Waiter) -> bool) {
+        let mut cursor = self.head;
+        while let Some(slot) = cursor {
+            let node = self.node(slot);
+            if at(&node.waiter) {
+                break;
+            }
+            cursor = node.next;
+        }
+        self.link_before(waiter, cursor);
+    }
+
+    /// Store `waiter` in a free slot, and link it in front of the node in `next`, or at the
+    /// back if `next` is `None`.
+    fn link_before(&mut self, waiter: Waiter, next: Option<usize>) {
+        let key = waiter.key;
+        let prev = match next {
+            Some(next) => self.node(next).prev,
+            None => self.tail,
+        };
+        let node = Slot::Occupied(Node { waiter, prev, next });
+        let slot = match self.free {
+            Some(slot) => {
+                match mem::replace(&mut self.slots[slot], node) {
+                    Slot::Vacant(next) => self.free = next,
+                    Slot::Occupied(_) => unreachable!("free slot was occupied"),
+                }
+                slot
+            }
+            None => {
+                self.slots.push(node);
+                self.slots.len() - 1
+            }
+        };
+        match prev {
+            Some(prev) => self.node_mut(prev).next = Some(slot),
+            None => self.head = Some(slot),
+        }
+        match next {
+            Some(next) => self.node_mut(next).prev = Some(slot),
+            None => self.tail = Some(slot),
+        }
+        let old = self.index.insert(key, slot);
+        debug_assert!(old.is_none());
+    }
+
+    pub(crate) fn front(&self) -> Option<&Waiter> {
+        self.head.map(|slot| &self.node(slot).waiter)
+    }
+
+    pub(crate) fn pop_front(&mut self) -> Option<Waiter> {
+        let slot = self.head?;
+        let waiter = self.unlink(slot);
+        self.index.remove(&waiter.key);
+        Some(waiter)
+    }
+
+    pub(crate) fn pop_back(&mut self) -> Option<Waiter> {
+        let slot = self.tail?;
+        let waiter = self.unlink(slot);
+        self.index.remove(&waiter.key);
+        Some(waiter)
+    }
+
+    pub(crate) fn contains(&self, key: usize) -> bool {
+        self.index.contains_key(&key)
+    }
+
+    pub(crate) fn get_mut(&mut self, key: usize) -> Option<&mut Waiter> {
+        let slot = *self.index.get(&key)?;
+        Some(&mut self.node_mut(slot).waiter)
+    }
+
+    pub(crate) fn remove(&mut self, key: usize) -> Option<Waiter> {
+        let slot = self.index.remove(&key)?;
+        Some(self.unlink(slot))
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.slots.clear();
+        self.free = None;
+        self.head = None;
+        self.tail = None;
+        self.index.clear();
+    }
+
+    /// Remove every waiter, passing each one to `f` in order, from the back if `from_back`
+    /// is set. This keeps the allocated storage, and is faster than popping the waiters one
+    /// at a time, since the index is cleared all at once.
+    pub(crate) fn drain(&mut self, from_back: bool, mut f: impl FnMut(Waiter)) {
+        let mut cursor = if from_back { self.tail } else { self.head };
+        while let Some(slot) = cursor {
+            let node = match mem::replace(&mut self.slots[slot], Slot::Vacant(None)) {
+                Slot::Occupied(node) => node,
+                Slot::Vacant(_) => unreachable!("queue linked to a free slot"),
+            };
+            cursor = if from_back { node.prev } else { node.next };
+            f(node.waiter);
+        }
+        self.clear();
+    }
+
+    /// Iterate over the waiters from front to back.
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &Waiter> {
+        let mut cursor = self.head;
+        core::iter::from_fn(move || {
+            let node = self.node(cursor?);
+            cursor = node.next;
+            Some(&node.waiter)
+        })
+    }
+
+    /// Iterate over the waiters from back to front.
+    pub(crate) fn iter_rev(&self) -> impl Iterator<Item = &Waiter> {
+        let mut cursor = self.tail;
+        core::iter::from_fn(move || {
+            let node = self.node(cursor?);
+            cursor = node.prev;
+            Some(&node.waiter)
+        })
+    }
+
+    pub(crate) fn retain(&mut self, mut f: impl FnMut(&Waiter) -> bool) {
+        let mut cursor = self.head;
+        while let Some(slot) = cursor {
+            let node = self.node(slot);
+            cursor = node.next;
+            if !f(&node.waiter) {
+                let waiter = self.unlink(slot);
+                self.index.remove(&waiter.key);
+            }
+        }
+    }
+
+    /// Remove and return the first waiter that matches `at`, and every waiter after it, in
+    /// order.
+    pub(crate) fn split_off(&mut self, at: impl Fn(&Waiter) -> bool) -> Vec<Waiter> {
+        let mut cursor = self.head;
+        while let Some(slot) = cursor {
+            let node = self.node(slot);
+            if at(&node.waiter) {
+                break;
+            }
+            cursor = node.next;
+        }
+        let mut split = Vec::new();
+        while let Some(slot) = cursor {
+            cursor = self.node(slot).next;
+            let waiter = self.unlink(slot);
+            self.index.remove(&waiter.key);
+            split.push(waiter);
+        }
+        split
+    }
+
+    /// Remove and return every waiter that matches `f`, in order.
+    pub(crate) fn extract(&mut self, f: impl Fn(&Waiter) -> bool) -> Vec<Waiter> {
+        let mut extracted = Vec::new();
+        let mut cursor = self.head;
+        while let Some(slot) = cursor {
+            let node = self.node(slot);
+            cursor = node.next;
+            if f(&node.waiter) {
+                let waiter = self.unlink(slot);
+                self.index.remove(&waiter.key);
+                extracted.push(waiter);
+            }
+        }
+        extracted
+    }
+
+    fn node(&self, slot: usize) -> &Node {
+        match &self.slots[slot] {
+            Slot::Occupied(node) => node,
+            Slot::Vacant(_) => unreachable!("queue linked to a free slot"),
+        }
+    }
+
+    fn node_mut(&mut self, slot: usize) -> &mut Node {
+        match &mut self.slots[slot] {
+            Slot::Occupied(node) => node,
+            Slot::Vacant(_) => unreachable!("queue linked to a free slot"),
+        }
+    }
+
+    /// Unlink the node in `slot` from the queue, and free the slot. This doesn't update
+    /// the index.
+    fn unlink(&mut self, slot: usize) -> Waiter {
+        let node = match mem::replace(&mut self.slots[slot], Slot::Vacant(self.free)) {
+            Slot::Occupied(node) => node,
+            Slot::Vacant(_) => unreachable!("unlinked a free slot"),
+        };
+        self.free = Some(slot);
+        match node.prev {
+            Some(prev) => self.node_mut(prev).next = node.next,
+            None => self.head = node.next,
+        }
+        match node.next {
+            Some(next) => self.node_mut(next).prev = node.prev,
+            None => self.tail = node.prev,
+        }
+        node.waiter
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use alloc::vec;
+    use futures_task::noop_waker;
+
+    fn waiter(key: usize) -> Waiter {
+        Waiter {
+            key,
+            waker: noop_waker(),
+            debounce: None,
+            token: 0,
+            #[cfg(feature = "timing")]
+            parked: std::time::Instant::now(),
+            #[cfg(feature = "timing")]
+            refreshed: std::time::Instant::now(),
+        }
+    }
+
+    fn keys(queue: &WaiterQueue) -> Vec<usize> {
+        queue.iter().map(|w| w.key).collect()
+    }
+
+    #[test]
+    fn reuses_slots_in_order() {
+        let mut queue = WaiterQueue::with_capacity(0);
+        for key in 0..5 {
+            queue.push_back(waiter(key));
+        }
+        assert_eq!(Some(3), queue.remove(3).map(|w| w.key));
+        assert_eq!(Some(0), queue.remove(0).map(|w| w.key));
+        assert!(queue.remove(0).is_none());
+        assert_eq!(vec![1, 2, 4], keys(&queue));
+
+        // new waiters go to the back, even though they reuse freed slots
+        queue.push_back(waiter(5));
+        queue.push_back(waiter(6));
+        assert_eq!(5, queue.slots.len());
+        assert_eq!(vec![1, 2, 4, 5, 6], keys(&queue));
+        assert_eq!(Some(1), queue.front().map(|w| w.key));
+
+        let split = queue.split_off(|w| w.key == 4);
+        assert_eq!(
+            vec![4, 5, 6],
+            split.iter().map(|w| w.key).collect::<Vec<_>>()
+        );
+        queue.insert_before(waiter(0), |w| w.key > 0);
+        queue.insert_before(waiter(3), |w| w.key > 3);
+        queue.insert_before(waiter(7), |w| w.key > 7);
+        assert_eq!(vec![0, 1, 2, 3, 7], keys(&queue));
+        assert_eq!(Some(7), queue.pop_back().map(|w| w.key));
+        assert_eq!(Some(0), queue.remove(0).map(|w| w.key));
+        assert_eq!(Some(3), queue.remove(3).map(|w| w.key));
+        assert_eq!(Some(1), queue.pop_front().map(|w| w.key));
+        assert_eq!(Some(2), queue.pop_front().map(|w| w.key));
+        assert!(queue.pop_front().is_none());
+        assert!(queue.is_empty());
+        assert!(queue.front().is_none());
+    }
+
+    #[test]
+    fn drain_in_order() {
+        let mut queue = WaiterQueue::with_capacity(0);
+        for key in 0..4 {
+            queue.push_back(waiter(key));
+        }
+        queue.remove(1);
+        let mut drained = Vec::new();
+        queue.drain(false, |w| drained.push(w.key));
+        assert_eq!(vec![0, 2, 3], drained);
+        assert!(queue.is_empty());
+        assert!(queue.front().is_none());
+
+        for key in 4..7 {
+            queue.push_back(waiter(key));
+        }
+        drained.clear();
+        queue.drain(true, |w| drained.push(w.key));
+        assert_eq!(vec![6, 5, 4], drained);
+        assert!(!queue.contains(4));
+        queue.push_back(waiter(7));
+        assert_eq!(vec![7], keys(&queue));
+    }
+}
@@ -0,0 +1,247 @@
+//! Waiting for the first of several [`Waitlist`]s to be notified.
+//!
+//! A task creates a single [`SelectToken`] and registers it on every waitlist it is
+//! interested in with [`Waitlist::register_select`]. The first of those waitlists to
+//! notify the token wakes the task and is recorded as the one that fired.
+//!
+//! Once the select is over, the token must be removed from the other waitlists with
+//! [`Waitlist::deregister_select`]. [`SelectGuard`] does that automatically when it is
+//! dropped.
+
+use alloc::sync::Arc;
+use alloc::task::Wake;
+use alloc::vec::Vec;
+use core::fmt;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::task::{Context, Waker};
+
+use crate::sync::Mutex;
+use crate::Waitlist;
+
+// Value of `fired` before any waitlist has notified the token
+const NOT_FIRED: usize = 0;
+
+// The id given to the next waitlist created, starting after `NOT_FIRED`
+static NEXT_ID: AtomicUsize = AtomicUsize::new(NOT_FIRED + 1);
+
+/// Return a new id to tell a waitlist apart from every other one in a token.
+///
+/// Ids are taken from a counter rather than the waitlist's address, so they stay the same
+/// when the waitlist is moved, and aren't shared with a waitlist dropped earlier.
+pub(crate) fn new_id() -> usize {
+    NEXT_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+/// A token that lets one task wait on several [`Waitlist`]s at once.
+pub struct SelectToken {
+    inner: Arc<TokenInner>,
+}
+
+struct TokenInner {
+    /// The id of the waitlist that fired first, or `NOT_FIRED`
+    fired: AtomicUsize,
+    waker: Mutex<Option<Waker>>,
+    /// The (waitlist id, key) pairs of the waitlists this token is registered on
+    registrations: Mutex<Vec<(usize, usize)>>,
+}
+
+/// The waker registered on a single waitlist on behalf of a token.
+struct Registration {
+    token: Arc<TokenInner>,
+    id: usize,
+}
+
+impl SelectToken {
+    /// Create a new token that hasn't been notified by any waitlist.
+    pub fn new() -> SelectToken {
+        SelectToken {
+            inner: Arc::new(TokenInner {
+                fired: AtomicUsize::new(NOT_FIRED),
+                waker: Mutex::new(None),
+                registrations: Mutex::new(Vec::new()),
+            }),
+        }
+    }
+
+    /// Set the waker of the task to wake when one of the waitlists fires.
+    ///
+    /// This should be called each time the selecting task is polled, and doesn't need to
+    /// be repeated for each waitlist the token is registered on.
+    pub fn set_context(&self, cx: &Context<'_>) {
+        let mut waker = self.inner.waker.lock();
+        match &*waker {
+            Some(w) if w.will_wake(cx.waker()) => {}
+            _ => *waker = Some(cx.waker().clone()),
+        }
+    }
+
+    /// Return true if any of the waitlists this token is registered on has notified it.
+    pub fn has_fired(&self) -> bool {
+        self.inner.fired.load(Ordering::Acquire) != NOT_FIRED
+    }
+
+    /// Return true if `waitlist` was the first waitlist to notify this token.
+    pub fn fired_from<T>(&self, waitlist: &Waitlist<T>) -> bool {
+        self.inner.fired.load(Ordering::Acquire) == waitlist.select_id()
+    }
+}
+
+impl Default for SelectToken {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for SelectToken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SelectToken")
+            .field("fired", &self.has_fired())
+            .finish()
+    }
+}
+
+/// Registers a [`SelectToken`] on several waitlists, and deregisters it from all of them
+/// when dropped.
+pub struct SelectGuard<'a, T = ()> {
+    token: SelectToken,
+    waitlists: Vec<&'a Waitlist<T>>,
+}
+
+impl<'a, T> SelectGuard<'a, T> {
+    /// Create a new guard with a fresh token that isn't registered on any waitlist yet.
+    pub fn new() -> SelectGuard<'a, T> {
+        SelectGuard {
+            token: SelectToken::new(),
+            waitlists: Vec::new(),
+        }
+    }
+
+    /// Register the guard's token on `waitlist`.
+    ///
+    /// See [`Waitlist::register_select`].
+    pub fn register(&mut self, waitlist: &'a Waitlist<T>) {
+        waitlist.register_select(&self.token);
+        self.waitlists.push(waitlist);
+    }
+
+    /// The token registered on all of the guard's waitlists.
+    pub fn token(&self) -> &SelectToken {
+        &self.token
+    }
+}
+
+impl<T> Default for SelectGuard<'_, T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> fmt::Debug for SelectGuard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SelectGuard")
+            .field("token", &self.token)
+            .field("registrations", &self.waitlists.len())
+            .finish()
+    }
+}
+
+impl<T> Drop for SelectGuard<'_, T> {
+    fn drop(&mut self) {
+        for waitlist in &self.waitlists {
+            waitlist.deregister_select(&self.token);
+        }
+    }
+}
+
+impl Wake for Registration {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref()
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        // Only the first waitlist to fire wakes the task. A later one still counts the token
+        // as notified, and passes the notification on when the token is deregistered.
+        let first = self
+            .token
+            .fired
+            .compare_exchange(NOT_FIRED, self.id, Ordering::AcqRel, Ordering::Acquire)
+            .is_ok();
+        if first {
+            // the task may be polled from inside its waker, and set the token's waker again
+            let waker = self.token.waker.lock().clone();
+            if let Some(waker) = waker {
+                waker.wake();
+            }
+        }
+    }
+}
+
+impl<T> Waitlist<T> {
+    /// Register `token` as waiting on this waitlist.
+    ///
+    /// The token is notified in FIFO order along with other waiters on this waitlist.
+    /// If this waitlist is the first one to notify the token, it is recorded as the one
+    /// that fired (see [`SelectToken::fired_from`]) and the token's task is woken. If
+    /// another waitlist already fired, the token's task isn't woken again, and the
+    /// notification is passed on to the next waiter on this waitlist once the token is
+    /// deregistered. If this waitlist was shut down with [`Waitlist::shutdown`], the token
+    /// is notified immediately.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the waitlist is at the waiter limit set by [`Waitlist::with_fixed_capacity`]
+    /// or [`Waitlist::with_max_waiters`].
+    pub fn register_select(&self, token: &SelectToken) {
+        let registration = Arc::new(Registration {
+            token: token.inner.clone(),
+            id: self.select_id(),
+        });
+        let mut inner = self.lock();
+        if inner.closed {
+            inner.to_wake.push(Waker::from(registration));
+            return;
+        }
+        assert!(!inner.is_full(), "waitlist is full");
+        let key = inner.insert_waker(Waker::from(registration));
+        token
+            .inner
+            .registrations
+            .lock()
+            .push((self.select_id(), key));
+    }
+
+    /// Remove `token` from this waitlist.
+    ///
+    /// If the token is still waiting, nobody is woken. If this waitlist is the one that fired,
+    /// its notification is marked as handled. If this waitlist notified the token after
+    /// another one fired, or before the select was cancelled, the token never handled that
+    /// notification, so it is passed on to the next waiter, like dropping a notified
+    /// [`WaitHandle`](crate::WaitHandle). If the token isn't registered on this waitlist,
+    /// this does nothing.
+    pub fn deregister_select(&self, token: &SelectToken) {
+        let id = self.select_id();
+        let mut keys = Vec::new();
+        token.inner.registrations.lock().retain(|&(i, key)| {
+            if i == id {
+                keys.push(key);
+            }
+            i != id
+        });
+        if !keys.is_empty() {
+            let mut inner = self.lock();
+            let fired = token.inner.fired.load(Ordering::Acquire) == id;
+            for key in keys {
+                if fired {
+                    inner.remove(key);
+                } else {
+                    inner.cancel(key);
+                }
+            }
+        }
+    }
+
+    /// The id that identifies this waitlist in a token's registrations.
+    fn select_id(&self) -> usize {
+        self.select_id
+    }
+}
@@ -0,0 +1,148 @@
+//! An async counting semaphore built on a [`Waitlist`].
+//!
+//! [`Semaphore`] keeps its permits in an atomic counter, so acquiring a permit that is
+//! available doesn't lock anything. Tasks that find no permit wait on a waitlist, and
+//! [`Semaphore::add_permits`] wakes them.
+//!
+//! # Waking waiters for new permits
+//!
+//! Adding `k` permits should wake up to `k` waiting tasks, but never more tasks than there
+//! are permits for. [`Waitlist::notify_n`] with `k` isn't quite right for that: tasks woken
+//! for earlier permits that haven't run yet would each get a new companion, and the extra
+//! tasks would only find the permits gone and wait again. Instead the semaphore calls
+//! [`Waitlist::notify_any_n`] with the number of permits now available, which counts the
+//! tasks that were already woken and haven't handled their notification yet, and only
+//! wakes the rest. Permits left over once the queue is empty stay in the counter for the
+//! next task to acquire.
+//!
+//! A woken task that finds its permit taken, for example by [`Semaphore::try_acquire`],
+//! registers again, and a woken task that is dropped before acquiring passes its
+//! notification on, so a permit never sits unused while a task is waiting for it.
+
+use core::fmt;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::task::{Context, Poll};
+
+use crate::{WaitHandle, Waitlist};
+
+/// A counting semaphore for async tasks.
+///
+/// See the [module level documentation](self) for details.
+pub struct Semaphore {
+    permits: AtomicUsize,
+    waitlist: Waitlist,
+}
+
+/// A permit acquired from a [`Semaphore`], which is returned to it when dropped.
+#[must_use = "the permit is released as soon as it is dropped"]
+pub struct Permit<'a> {
+    semaphore: &'a Semaphore,
+}
+
+/// Future returned by [`Semaphore::acquire`].
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct Acquire<'a> {
+    semaphore: &'a Semaphore,
+    handle: WaitHandle<'a>,
+}
+
+impl Semaphore {
+    /// Create a new `Semaphore` with `permits` permits available.
+    pub fn new(permits: usize) -> Semaphore {
+        Semaphore {
+            permits: AtomicUsize::new(permits),
+            waitlist: Waitlist::new(),
+        }
+    }
+
+    /// Return the number of permits that can be acquired right now.
+    pub fn available_permits(&self) -> usize {
+        self.permits.load(Ordering::Relaxed)
+    }
+
+    /// Acquire a permit if one is available, without waiting.
+    pub fn try_acquire(&self) -> Option<Permit<'_>> {
+        let mut permits = self.permits.load(Ordering::Relaxed);
+        while permits > 0 {
+            match self.permits.compare_exchange_weak(
+                permits,
+                permits - 1,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return Some(Permit { semaphore: self }),
+                Err(current) => permits = current,
+            }
+        }
+        None
+    }
+
+    /// Wait for a permit.
+    ///
+    /// Tasks waiting for a permit get one in the order they started waiting, unless a
+    /// permit is taken by [`Semaphore::try_acquire`], or by a task that polls `acquire`
+    /// just as a permit is released, before the woken task gets to it.
+    pub fn acquire(&self) -> Acquire<'_> {
+        Acquire {
+            semaphore: self,
+            handle: self.waitlist.wait(),
+        }
+    }
+
+    /// Add `k` permits, and wake up to `k` tasks waiting for them.
+    ///
+    /// Returns the number of tasks woken.
+    pub fn add_permits(&self, k: usize) -> usize {
+        if k == 0 {
+            return 0;
+        }
+        let permits = self.permits.fetch_add(k, Ordering::Release) + k;
+        self.waitlist.notify_any_n(permits)
+    }
+}
+
+impl fmt::Debug for Semaphore {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Semaphore")
+            .field("permits", &self.available_permits())
+            .finish()
+    }
+}
+
+impl Permit<'_> {
+    /// Keep the permit out of the semaphore, instead of returning it when dropped.
+    pub fn forget(self) {
+        core::mem::forget(self);
+    }
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        self.semaphore.add_permits(1);
+    }
+}
+
+impl fmt::Debug for Permit<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Permit").finish()
+    }
+}
+
+impl<'a> Future for Acquire<'a> {
+    type Output = Permit<'a>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Permit<'a>> {
+        let semaphore = self.semaphore;
+        self.handle.poll_until(cx, || semaphore.try_acquire())
+    }
+}
+
+impl fmt::Debug for Acquire<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Acquire")
+            .field("key", &self.handle.key())
+            .finish()
+    }
+}
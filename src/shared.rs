@@ -0,0 +1,92 @@
+//! A reference counted [`Waitlist`] that can be cloned.
+//!
+//! Every method of [`Waitlist`] takes `&self`, so the usual way to share one between tasks
+//! is to put it in an `Arc`. [`SharedWaitlist`] is that `Arc`, with the waitlist's
+//! methods available through `Deref`:
+//!
+//! ```
+//! # use waitlist::shared::SharedWaitlist;
+//! let waitlist = SharedWaitlist::new();
+//! let notifier = waitlist.clone();
+//! std::thread::spawn(move || {
+//!     notifier.notify_all();
+//! })
+//! .join()
+//! .unwrap();
+//! ```
+
+use alloc::sync::Arc;
+use core::fmt;
+use core::ops::Deref;
+
+use crate::Waitlist;
+
+/// A [`Waitlist`] shared through an `Arc`.
+///
+/// Cloning it gives another reference to the same waitlist.
+pub struct SharedWaitlist<T = ()>(Arc<Waitlist<T>>);
+
+impl SharedWaitlist {
+    /// Create a new, empty shared waitlist.
+    ///
+    /// Use `SharedWaitlist::default()` for a waitlist with payloads, or convert a
+    /// [`Waitlist`] made with one of its other constructors using `From`.
+    #[inline]
+    pub fn new() -> SharedWaitlist {
+        SharedWaitlist(Arc::new(Waitlist::new()))
+    }
+}
+
+impl<T> SharedWaitlist<T> {
+    /// Return true if both refer to the same waitlist.
+    #[inline]
+    pub fn ptr_eq(this: &SharedWaitlist<T>, other: &SharedWaitlist<T>) -> bool {
+        Arc::ptr_eq(&this.0, &other.0)
+    }
+}
+
+impl<T> Clone for SharedWaitlist<T> {
+    #[inline]
+    fn clone(&self) -> Self {
+        SharedWaitlist(self.0.clone())
+    }
+}
+
+impl<T> Deref for SharedWaitlist<T> {
+    type Target = Waitlist<T>;
+
+    #[inline]
+    fn deref(&self) -> &Waitlist<T> {
+        &self.0
+    }
+}
+
+impl<T> Default for SharedWaitlist<T> {
+    fn default() -> Self {
+        SharedWaitlist(Arc::new(Waitlist::default()))
+    }
+}
+
+impl<T> From<Waitlist<T>> for SharedWaitlist<T> {
+    fn from(waitlist: Waitlist<T>) -> Self {
+        SharedWaitlist(Arc::new(waitlist))
+    }
+}
+
+impl<T> From<Arc<Waitlist<T>>> for SharedWaitlist<T> {
+    fn from(waitlist: Arc<Waitlist<T>>) -> Self {
+        SharedWaitlist(waitlist)
+    }
+}
+
+impl<T> From<SharedWaitlist<T>> for Arc<Waitlist<T>> {
+    fn from(shared: SharedWaitlist<T>) -> Self {
+        shared.0
+    }
+}
+
+impl<T> fmt::Debug for SharedWaitlist<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("SharedWaitlist").field(&*self.0).finish()
+    }
+}
@@ -0,0 +1,99 @@
+//! The mutex and atomics used for the waitlist's state.
+//!
+//! With the `std` feature the mutex is `std::sync::Mutex`, or `parking_lot::Mutex` if the
+//! `parking_lot` feature is enabled too. Without `std` it is a spin lock from the `spin`
+//! crate. A panic while the lock is held can't leave the state protected by it
+//! inconsistent, so poisoning is ignored.
+//!
+//! When built with `--cfg loom`, the mutex, the atomic flags and their fences come from `loom` instead,
+//! so that `tests/loom.rs` can check every interleaving of them. Those types only work
+//! inside `loom::model`.
+
+#[cfg(loom)]
+pub(crate) use loom::sync::atomic::{fence, AtomicUsize};
+
+#[cfg(not(loom))]
+pub(crate) use core::sync::atomic::{fence, AtomicUsize};
+
+pub(crate) use imp::MutexGuard;
+
+#[cfg(loom)]
+mod imp {
+    pub(crate) use loom::sync::{Mutex as Lock, MutexGuard};
+
+    pub(crate) fn lock<T>(lock: &Lock<T>) -> MutexGuard<'_, T> {
+        lock.lock().unwrap()
+    }
+
+    pub(crate) fn try_lock<T>(lock: &Lock<T>) -> Option<MutexGuard<'_, T>> {
+        lock.try_lock().ok()
+    }
+}
+
+#[cfg(all(not(loom), feature = "parking_lot"))]
+mod imp {
+    pub(crate) use parking_lot::{Mutex as Lock, MutexGuard};
+
+    pub(crate) fn lock<T>(lock: &Lock<T>) -> MutexGuard<'_, T> {
+        lock.lock()
+    }
+
+    pub(crate) fn try_lock<T>(lock: &Lock<T>) -> Option<MutexGuard<'_, T>> {
+        lock.try_lock()
+    }
+}
+
+#[cfg(all(not(loom), feature = "std", not(feature = "parking_lot")))]
+mod imp {
+    use std::sync::{PoisonError, TryLockError};
+
+    pub(crate) use std::sync::{Mutex as Lock, MutexGuard};
+
+    pub(crate) fn lock<T>(lock: &Lock<T>) -> MutexGuard<'_, T> {
+        lock.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub(crate) fn try_lock<T>(lock: &Lock<T>) -> Option<MutexGuard<'_, T>> {
+        match lock.try_lock() {
+            Ok(guard) => Some(guard),
+            Err(TryLockError::Poisoned(err)) => Some(err.into_inner()),
+            Err(TryLockError::WouldBlock) => None,
+        }
+    }
+}
+
+#[cfg(all(not(loom), not(feature = "std")))]
+mod imp {
+    pub(crate) use spin::{Mutex as Lock, MutexGuard};
+
+    pub(crate) fn lock<T>(lock: &Lock<T>) -> MutexGuard<'_, T> {
+        lock.lock()
+    }
+
+    pub(crate) fn try_lock<T>(lock: &Lock<T>) -> Option<MutexGuard<'_, T>> {
+        lock.try_lock()
+    }
+}
+
+pub(crate) struct Mutex<T>(imp::Lock<T>);
+
+impl<T> Mutex<T> {
+    #[cfg(not(loom))]
+    pub(crate) const fn new(value: T) -> Mutex<T> {
+        Mutex(imp::Lock::new(value))
+    }
+
+    #[cfg(loom)]
+    pub(crate) fn new(value: T) -> Mutex<T> {
+        Mutex(imp::Lock::new(value))
+    }
+
+    pub(crate) fn lock(&self) -> MutexGuard<'_, T> {
+        imp::lock(&self.0)
+    }
+
+    /// Lock the mutex if that can be done without waiting.
+    pub(crate) fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        imp::try_lock(&self.0)
+    }
+}
@@ -0,0 +1,75 @@
+mod mock_waker;
+
+use std::thread;
+
+use mock_waker::MockWaker;
+use waitlist::*;
+
+#[test]
+fn cancel_from_another_thread() {
+    let waitlist = Waitlist::new();
+    let waker = MockWaker::new();
+    let other = MockWaker::new();
+    let mut handle = waitlist.wait();
+    handle.set_context(&waker.to_context());
+    let mut next = waitlist.wait();
+    next.set_context(&other.to_context());
+
+    let token = handle.cancel_token();
+    thread::scope(|s| {
+        s.spawn(move || assert!(token.cancel()));
+    });
+    // the cancelled task was woken so it can notice, but nobody else was
+    assert_eq!(1, waker.notified_count());
+    assert_eq!(0, other.notified_count());
+    assert!(!token.cancel());
+
+    assert!(!handle.finish());
+    assert!(waitlist.notify_one());
+    assert_eq!(1, other.notified_count());
+    assert!(next.finish());
+    assert!(!waitlist.notify_one());
+}
+
+#[test]
+fn token_after_completion_is_noop() {
+    let waitlist = Waitlist::new();
+    let waker = MockWaker::new();
+    let mut handle = waitlist.wait();
+    assert!(!handle.cancel_token().cancel());
+
+    handle.set_context(&waker.to_context());
+    let token = handle.cancel_token();
+    assert!(waitlist.notify_one());
+    // already notified, so the task handles the notification itself
+    assert!(!token.cancel());
+    assert!(handle.finish());
+
+    handle.set_context(&waker.to_context());
+    let token = handle.cancel_token();
+    assert!(!handle.finish());
+    assert!(!token.cancel());
+}
+
+#[test]
+fn token_races_finish() {
+    for _ in 0..100 {
+        let waitlist = Waitlist::new();
+        let waker = MockWaker::new();
+        let mut handle = waitlist.wait();
+        handle.set_context(&waker.to_context());
+        let token = handle.cancel_token();
+        thread::scope(|s| {
+            s.spawn(move || token.cancel());
+            handle.finish();
+        });
+
+        // either way, the waitlist is left empty and consistent
+        assert!(!handle.is_pending());
+        assert!(!waitlist.notify_one());
+        let mut fresh = waitlist.wait();
+        fresh.set_context(&waker.to_context());
+        assert!(waitlist.notify_one());
+        assert!(fresh.finish());
+    }
+}
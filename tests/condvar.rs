@@ -0,0 +1,78 @@
+mod mock_waker;
+
+use std::cell::Cell;
+use std::task::Poll;
+
+use mock_waker::{poll, MockWaker};
+use waitlist::condvar::AsyncCondvar;
+
+#[test]
+fn ready_without_waiting() {
+    let condvar = AsyncCondvar::new();
+    let waker = MockWaker::new();
+    assert_eq!(
+        Poll::Ready(()),
+        poll(&mut condvar.wait_until(|| true), &waker)
+    );
+    assert!(!condvar.notify_one());
+}
+
+#[test]
+fn spurious_wakeup_waits_again() {
+    let condvar = AsyncCondvar::new();
+    let value = Cell::new(0);
+    let waker = MockWaker::new();
+    let mut fut = condvar.wait_until(|| value.get() >= 2);
+    assert_eq!(Poll::Pending, poll(&mut fut, &waker));
+
+    value.set(1);
+    assert!(condvar.notify_one());
+    assert_eq!(1, waker.notified_count());
+    assert_eq!(Poll::Pending, poll(&mut fut, &waker));
+
+    // the future registered again, so the next notification still reaches it
+    value.set(2);
+    assert!(condvar.notify_one());
+    assert_eq!(2, waker.notified_count());
+    assert_eq!(Poll::Ready(()), poll(&mut fut, &waker));
+    assert!(!condvar.notify_all());
+}
+
+#[test]
+fn notify_all_wakes_every_waiter() {
+    let condvar = AsyncCondvar::new();
+    let ready = Cell::new(false);
+    let wakers: [MockWaker; 3] = Default::default();
+    let mut futs: Vec<_> = wakers
+        .iter()
+        .map(|_| condvar.wait_until(|| ready.get()))
+        .collect();
+    for (fut, waker) in futs.iter_mut().zip(&wakers) {
+        assert_eq!(Poll::Pending, poll(fut, waker));
+    }
+
+    ready.set(true);
+    assert!(condvar.notify_all());
+    for (fut, waker) in futs.iter_mut().zip(&wakers) {
+        assert_eq!(1, waker.notified_count());
+        assert_eq!(Poll::Ready(()), poll(fut, waker));
+    }
+}
+
+#[test]
+fn dropping_woken_future_passes_on_notification() {
+    let condvar = AsyncCondvar::new();
+    let ready = Cell::new(false);
+    let w1 = MockWaker::new();
+    let w2 = MockWaker::new();
+    let mut f1 = condvar.wait_until(|| ready.get());
+    let mut f2 = condvar.wait_until(|| ready.get());
+    assert_eq!(Poll::Pending, poll(&mut f1, &w1));
+    assert_eq!(Poll::Pending, poll(&mut f2, &w2));
+
+    ready.set(true);
+    assert!(condvar.notify_one());
+    drop(f1);
+    assert_eq!(1, w2.notified_count());
+    assert_eq!(Poll::Ready(()), poll(&mut f2, &w2));
+}
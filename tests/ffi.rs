@@ -0,0 +1,64 @@
+#![cfg(feature = "ffi")]
+
+use std::ffi::c_void;
+use std::mem::MaybeUninit;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use waitlist::ffi::*;
+
+#[derive(Default)]
+struct Counts {
+    woken: AtomicUsize,
+    released: AtomicUsize,
+}
+
+extern "C" fn wake(context: *mut c_void) {
+    let counts = unsafe { &*(context as *const Counts) };
+    counts.woken.fetch_add(1, Ordering::SeqCst);
+}
+
+extern "C" fn release(context: *mut c_void) {
+    let counts = unsafe { &*(context as *const Counts) };
+    counts.released.fetch_add(1, Ordering::SeqCst);
+}
+
+fn new_key() -> WaitlistKey {
+    let mut key = MaybeUninit::uninit();
+    unsafe {
+        waitlist_key_init(key.as_mut_ptr());
+        key.assume_init()
+    }
+}
+
+#[test]
+fn callbacks_as_wakers() {
+    let first = Counts::default();
+    let second = Counts::default();
+    let context = |c: &Counts| c as *const Counts as *mut c_void;
+    unsafe {
+        let waitlist = waitlist_new();
+        let mut k1 = new_key();
+        let mut k2 = new_key();
+        waitlist_register(waitlist, &mut k1, wake, Some(release), context(&first));
+        waitlist_register(waitlist, &mut k2, wake, Some(release), context(&second));
+        assert!(k1.pending && k2.pending);
+
+        assert!(waitlist_notify_one(waitlist));
+        assert_eq!(1, first.woken.load(Ordering::SeqCst));
+        assert_eq!(0, second.woken.load(Ordering::SeqCst));
+        // the waker is released once it has been woken
+        assert_eq!(1, first.released.load(Ordering::SeqCst));
+        assert!(waitlist_finish(waitlist, &mut k1));
+        assert!(!k1.pending);
+
+        // re-registering replaces and releases the previous callback
+        waitlist_register(waitlist, &mut k2, wake, None, context(&second));
+        assert_eq!(1, second.released.load(Ordering::SeqCst));
+
+        assert!(waitlist_notify_all(waitlist));
+        assert_eq!(1, second.woken.load(Ordering::SeqCst));
+        assert!(!waitlist_cancel(waitlist, &mut k2));
+        assert!(!waitlist_notify_one(waitlist));
+        waitlist_free(waitlist);
+    }
+}
@@ -0,0 +1,110 @@
+#![cfg(feature = "lockfree")]
+
+mod mock_waker;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+use mock_waker::MockWaker;
+use waitlist::lockfree::*;
+
+fn wait_for_waker<'a>(waitlist: &'a LockFreeWaitlist, waker: &MockWaker) -> LockFreeWaitHandle<'a> {
+    let mut handle = waitlist.wait();
+    handle.set_context(&waker.to_context());
+    handle
+}
+
+#[test]
+fn notify_in_order() {
+    let waitlist = LockFreeWaitlist::new();
+    let wakers: [MockWaker; 3] = Default::default();
+    let mut handles: Vec<_> = wakers
+        .iter()
+        .map(|w| wait_for_waker(&waitlist, w))
+        .collect();
+
+    assert!(waitlist.notify_one());
+    assert_eq!(1, wakers[0].notified_count());
+    assert_eq!(0, wakers[1].notified_count());
+    assert!(handles[0].is_notified());
+    assert!(handles[0].finish());
+
+    assert_eq!(2, waitlist.notify_all());
+    assert!(!waitlist.notify_one());
+    assert!(handles.iter_mut().skip(1).all(|h| h.finish()));
+    let counts: Vec<_> = wakers.iter().map(|w| w.notified_count()).collect();
+    assert_eq!(vec![1, 1, 1], counts);
+}
+
+#[test]
+fn finished_waiters_are_skipped() {
+    let waitlist = LockFreeWaitlist::new();
+    let wakers: [MockWaker; 2] = Default::default();
+    let mut first = wait_for_waker(&waitlist, &wakers[0]);
+    let mut second = wait_for_waker(&waitlist, &wakers[1]);
+
+    assert!(!first.finish());
+    assert!(waitlist.notify_one());
+    assert_eq!(0, wakers[0].notified_count());
+    assert_eq!(1, wakers[1].notified_count());
+    assert!(second.finish());
+    assert!(!waitlist.notify_one());
+}
+
+#[test]
+fn dropped_handle_passes_on_notification() {
+    let waitlist = LockFreeWaitlist::new();
+    let wakers: [MockWaker; 2] = Default::default();
+    let first = wait_for_waker(&waitlist, &wakers[0]);
+    let mut second = wait_for_waker(&waitlist, &wakers[1]);
+
+    assert!(waitlist.notify_one());
+    drop(first);
+    assert_eq!(1, wakers[1].notified_count());
+    assert!(second.finish());
+}
+
+#[test]
+fn reregistering_after_notify_waits_again() {
+    let waitlist = LockFreeWaitlist::new();
+    let waker = MockWaker::new();
+    let mut handle = wait_for_waker(&waitlist, &waker);
+    // updating the waker keeps the waiter's place
+    handle.set_context(&waker.to_context());
+    assert!(waitlist.notify_one());
+    assert!(!waitlist.notify_one());
+
+    handle.set_context(&waker.to_context());
+    assert!(!handle.is_notified());
+    assert!(waitlist.notify_one());
+    assert_eq!(2, waker.notified_count());
+    assert!(handle.finish());
+}
+
+#[test]
+fn every_notification_wakes_one_waiter() {
+    const THREADS: usize = 4;
+    const WAITS: usize = 1_000;
+
+    let waitlist = LockFreeWaitlist::new();
+    let sent = AtomicUsize::new(0);
+    let received = AtomicUsize::new(0);
+    thread::scope(|s| {
+        for _ in 0..THREADS {
+            s.spawn(|| {
+                let waker = MockWaker::new();
+                for _ in 0..WAITS {
+                    let mut handle = wait_for_waker(&waitlist, &waker);
+                    if waitlist.notify_one() {
+                        sent.fetch_add(1, Ordering::Relaxed);
+                    }
+                    if handle.finish() {
+                        received.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+            });
+        }
+    });
+    assert_eq!(sent.into_inner(), received.into_inner());
+    assert!(!waitlist.notify_one());
+}
@@ -0,0 +1,147 @@
+//! Model checked tests of the lock-free checks in the `notify_*` methods.
+//!
+//! Run with `RUSTFLAGS="--cfg loom" cargo test --release --test loom`.
+#![cfg(loom)]
+
+use std::task::{Context, Wake, Waker};
+
+use loom::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use loom::sync::Arc;
+use loom::thread;
+use waitlist::Waitlist;
+
+/// A waker that counts how many times it was woken.
+#[derive(Default)]
+struct CountingWaker(AtomicUsize);
+
+impl Wake for CountingWaker {
+    fn wake(self: std::sync::Arc<Self>) {
+        self.wake_by_ref()
+    }
+
+    fn wake_by_ref(self: &std::sync::Arc<Self>) {
+        self.0.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+fn counting_waker() -> (std::sync::Arc<CountingWaker>, Waker) {
+    let counter = std::sync::Arc::new(CountingWaker::default());
+    let waker = Waker::from(counter.clone());
+    (counter, waker)
+}
+
+/// Check that a task that registers and then checks a relaxed `ready` flag can't miss a
+/// notification sent with `notify` after setting it.
+fn assert_notification_not_lost(notify: fn(&Waitlist)) {
+    loom::model(move || {
+        let waitlist = Arc::new(Waitlist::new());
+        let ready = Arc::new(AtomicBool::new(false));
+        let notifier = {
+            let (waitlist, ready) = (waitlist.clone(), ready.clone());
+            thread::spawn(move || {
+                ready.store(true, Ordering::Relaxed);
+                notify(&waitlist);
+            })
+        };
+
+        // register, then check the condition, like a future waiting for `ready` would. The
+        // waitlist's fences make this work even with relaxed accesses to `ready`.
+        let (counter, waker) = counting_waker();
+        let mut handle = waitlist.wait();
+        handle.set_context(&Context::from_waker(&waker));
+        let saw_ready = ready.load(Ordering::Relaxed);
+        notifier.join().unwrap();
+
+        assert!(
+            saw_ready || counter.0.load(Ordering::SeqCst) == 1,
+            "the waiter missed the event, and wasn't woken for it"
+        );
+    });
+}
+
+#[test]
+fn notification_not_lost() {
+    assert_notification_not_lost(|waitlist| {
+        waitlist.notify_one();
+    });
+}
+
+#[test]
+fn notify_any_not_lost() {
+    assert_notification_not_lost(|waitlist| {
+        waitlist.notify_any();
+    });
+}
+
+#[test]
+fn notify_all_not_lost() {
+    assert_notification_not_lost(|waitlist| {
+        waitlist.notify_all();
+    });
+}
+
+#[test]
+fn notify_any_wakes_one() {
+    loom::model(|| {
+        let waitlist = Arc::new(Waitlist::new());
+        let (counters, wakers): (Vec<_>, Vec<_>) = (0..2).map(|_| counting_waker()).unzip();
+        let _handles: Vec<_> = wakers
+            .iter()
+            .map(|waker| {
+                let mut handle = waitlist.wait();
+                handle.set_context(&Context::from_waker(waker));
+                handle
+            })
+            .collect();
+
+        let notifiers: Vec<_> = (0..2)
+            .map(|_| {
+                let waitlist = waitlist.clone();
+                thread::spawn(move || {
+                    waitlist.notify_any();
+                })
+            })
+            .collect();
+        for notifier in notifiers {
+            notifier.join().unwrap();
+        }
+
+        let woken: usize = counters.iter().map(|c| c.0.load(Ordering::SeqCst)).sum();
+        assert_eq!(1, woken, "nobody handled the first notification");
+    });
+}
+
+#[test]
+fn notify_any_sees_concurrent_finish() {
+    loom::model(|| {
+        let waitlist = Arc::new(Waitlist::new());
+        let ready = Arc::new(AtomicBool::new(false));
+        let (first_counter, first_waker) = counting_waker();
+        let mut first = waitlist.wait();
+        first.set_context(&Context::from_waker(&first_waker));
+        assert!(waitlist.notify_any());
+        assert_eq!(1, first_counter.0.load(Ordering::SeqCst));
+
+        let notifier = {
+            let (waitlist, ready) = (waitlist.clone(), ready.clone());
+            thread::spawn(move || {
+                ready.store(true, Ordering::Relaxed);
+                waitlist.notify_any();
+            })
+        };
+
+        // the notified task handles its notification, and waits again with a new handle,
+        // before checking the condition
+        assert!(first.finish());
+        let (counter, waker) = counting_waker();
+        let mut second = waitlist.wait();
+        second.set_context(&Context::from_waker(&waker));
+        let saw_ready = ready.load(Ordering::Relaxed);
+        notifier.join().unwrap();
+
+        assert!(
+            saw_ready || counter.0.load(Ordering::SeqCst) == 1,
+            "notify_any saw the old notification, and the new waiter missed the event"
+        );
+    });
+}
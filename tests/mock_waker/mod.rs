@@ -1,6 +1,8 @@
+use core::future::Future;
 use core::mem;
+use core::pin::Pin;
 use core::sync::atomic::{AtomicUsize, Ordering};
-use core::task::{Context, RawWaker, RawWakerVTable, Waker};
+use core::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
 use std::sync::Arc;
 
 pub struct MockWaker {
@@ -35,6 +37,12 @@ impl Default for MockWaker {
     }
 }
 
+/// Poll `fut` once with a context for `waker`.
+#[allow(dead_code)] // not every test binary polls futures
+pub fn poll<F: Future + Unpin>(fut: &mut F, waker: &MockWaker) -> Poll<F::Output> {
+    Pin::new(fut).poll(&mut waker.to_context())
+}
+
 fn make_waker(inner: &Inner) -> Waker {
     let data = Arc::into_raw(inner.clone()) as *const ();
     let raw = RawWaker::new(data, &VTABLE);
@@ -0,0 +1,75 @@
+mod mock_waker;
+
+use std::task::Poll;
+
+use mock_waker::{poll, MockWaker};
+use waitlist::notify::Notify;
+
+#[test]
+fn permit_before_wait() {
+    let notify = Notify::new();
+    let waker = MockWaker::new();
+    notify.notify_one();
+    notify.notify_one();
+
+    assert_eq!(Poll::Ready(()), poll(&mut notify.notified(), &waker));
+    // only a single permit is stored
+    assert_eq!(Poll::Pending, poll(&mut notify.notified(), &waker));
+}
+
+#[test]
+fn notify_one_wakes_in_order() {
+    let notify = Notify::new();
+    let w1 = MockWaker::new();
+    let w2 = MockWaker::new();
+    let mut f1 = notify.notified();
+    let mut f2 = notify.notified();
+    assert_eq!(Poll::Pending, poll(&mut f1, &w1));
+    assert_eq!(Poll::Pending, poll(&mut f2, &w2));
+
+    notify.notify_one();
+    assert_eq!(1, w1.notified_count());
+    assert_eq!(0, w2.notified_count());
+    assert_eq!(Poll::Ready(()), poll(&mut f1, &w1));
+    assert_eq!(Poll::Pending, poll(&mut f2, &w2));
+}
+
+#[test]
+fn notify_waiters_includes_unpolled() {
+    let notify = Notify::new();
+    let waker = MockWaker::new();
+    let mut polled = notify.notified();
+    let mut unpolled = notify.notified();
+    assert_eq!(Poll::Pending, poll(&mut polled, &waker));
+
+    notify.notify_waiters();
+    let mut after = notify.notified();
+    assert_eq!(1, waker.notified_count());
+    assert_eq!(Poll::Ready(()), poll(&mut polled, &waker));
+    assert_eq!(Poll::Ready(()), poll(&mut unpolled, &waker));
+    // notify_waiters doesn't store a permit
+    assert_eq!(Poll::Pending, poll(&mut after, &waker));
+}
+
+#[test]
+fn dropped_notified_passes_on_notification() {
+    let notify = Notify::new();
+    let w1 = MockWaker::new();
+    let w2 = MockWaker::new();
+    let mut f1 = notify.notified();
+    let mut f2 = notify.notified();
+    assert_eq!(Poll::Pending, poll(&mut f1, &w1));
+    assert_eq!(Poll::Pending, poll(&mut f2, &w2));
+
+    notify.notify_one();
+    drop(f1);
+    assert_eq!(1, w2.notified_count());
+    assert_eq!(Poll::Ready(()), poll(&mut f2, &w2));
+
+    // with nobody left to pass it to, the notification becomes the permit
+    let mut f3 = notify.notified();
+    assert_eq!(Poll::Pending, poll(&mut f3, &w1));
+    notify.notify_one();
+    drop(f3);
+    assert_eq!(Poll::Ready(()), poll(&mut notify.notified(), &w1));
+}
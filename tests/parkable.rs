@@ -0,0 +1,26 @@
+mod mock_waker;
+
+use mock_waker::MockWaker;
+use waitlist::parkable::Parkable;
+use waitlist::Waitlist;
+
+fn park_two(parkable: &dyn Parkable, w1: &MockWaker, w2: &MockWaker) {
+    let mut t1 = parkable.register(&w1.to_context());
+    let t2 = parkable.register(&w2.to_context());
+    assert!(t1.is_pending());
+
+    assert!(parkable.notify_one());
+    assert_eq!(1, w1.notified_count());
+    assert!(t1.try_finish(&mut w1.to_context()));
+
+    // dropping the token cancels the wait
+    drop(t2);
+    assert!(!parkable.notify_one());
+    assert_eq!(0, w2.notified_count());
+}
+
+#[test]
+fn waitlist_as_dyn_parkable() {
+    let waitlist = Waitlist::new();
+    park_two(&waitlist, &MockWaker::new(), &MockWaker::new());
+}
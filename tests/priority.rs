@@ -0,0 +1,133 @@
+mod mock_waker;
+
+use mock_waker::MockWaker;
+use waitlist::priority::*;
+
+fn wait_with_priority<'a>(
+    waitlist: &'a PriorityWaitlist,
+    waker: &MockWaker,
+    priority: u64,
+) -> PriorityWaitHandle<'a> {
+    let mut handle = waitlist.wait();
+    handle.set_context(&waker.to_context(), priority);
+    handle
+}
+
+#[test]
+fn highest_priority_first() {
+    let waitlist = PriorityWaitlist::new();
+    let priorities = [3, 9, 1, 7, 5];
+    let wakers: Vec<MockWaker> = priorities.iter().map(|_| MockWaker::new()).collect();
+    let _handles: Vec<_> = wakers
+        .iter()
+        .zip(priorities)
+        .map(|(w, p)| wait_with_priority(&waitlist, w, p))
+        .collect();
+
+    for expected in [1, 3, 4, 0, 2] {
+        assert!(waitlist.notify_one());
+        assert_eq!(1, wakers[expected].notified_count(), "waker {}", expected);
+    }
+    assert!(!waitlist.notify_one());
+    let counts: Vec<_> = wakers.iter().map(|w| w.notified_count()).collect();
+    assert_eq!(vec![1; 5], counts);
+}
+
+#[test]
+fn equal_priorities_are_fifo() {
+    let waitlist = PriorityWaitlist::new();
+    let wakers: [MockWaker; 6] = Default::default();
+    let _handles: Vec<_> = wakers
+        .iter()
+        .enumerate()
+        .map(|(i, w)| wait_with_priority(&waitlist, w, (i % 2) as u64))
+        .collect();
+
+    // odd wakers have the higher priority
+    for (i, &expected) in [1, 3, 5, 0, 2, 4].iter().enumerate() {
+        assert!(waitlist.notify_one());
+        assert_eq!(1, wakers[expected].notified_count(), "notification {}", i);
+    }
+}
+
+#[test]
+fn cancel_and_reprioritize_from_the_middle() {
+    let waitlist = PriorityWaitlist::new();
+    let wakers: Vec<MockWaker> = (0..32).map(|_| MockWaker::new()).collect();
+    let mut handles: Vec<_> = wakers
+        .iter()
+        .enumerate()
+        .map(|(i, w)| wait_with_priority(&waitlist, w, (i * 7 % 32) as u64))
+        .collect();
+    for handle in handles.iter_mut().step_by(3) {
+        assert!(!handle.cancel());
+    }
+    // raise waker 1 above everything else
+    handles[1].set_context(&wakers[1].to_context(), 100);
+    assert_eq!(21, waitlist.len());
+
+    assert!(waitlist.notify_one());
+    assert_eq!(1, wakers[1].notified_count());
+    let mut expected: Vec<_> = (0..32).filter(|i| i % 3 != 0 && *i != 1).collect();
+    expected.sort_by_key(|i| std::cmp::Reverse(i * 7 % 32));
+    for i in expected {
+        assert!(waitlist.notify_one());
+        assert_eq!(1, wakers[i].notified_count(), "waker {}", i);
+    }
+    assert!(waitlist.is_empty());
+    for (i, w) in wakers.iter().enumerate() {
+        assert_eq!(usize::from(i % 3 != 0), w.notified_count(), "waker {}", i);
+    }
+}
+
+#[test]
+fn cancel_passes_on_notification() {
+    let waitlist = PriorityWaitlist::new();
+    let (high, low) = (MockWaker::new(), MockWaker::new());
+    let mut first = wait_with_priority(&waitlist, &high, 2);
+    let mut second = wait_with_priority(&waitlist, &low, 1);
+
+    assert!(waitlist.notify_any());
+    assert!(!waitlist.notify_any());
+    assert_eq!(1, high.notified_count());
+    assert!(first.cancel());
+    assert_eq!(1, low.notified_count());
+    assert!(second.finish());
+    assert!(!first.is_pending());
+}
+
+/// A waker that notifies the next task on the same waitlist when it is woken.
+struct ChainWaker(std::sync::Arc<PriorityWaitlist>, MockWaker);
+
+impl std::task::Wake for ChainWaker {
+    fn wake(self: std::sync::Arc<Self>) {
+        self.1.to_context().waker().wake_by_ref();
+        self.0.notify_one();
+    }
+}
+
+#[test]
+fn waker_can_reenter_waitlist() {
+    use std::sync::Arc;
+    use std::task::{Context, Waker};
+
+    let waitlist = Arc::new(PriorityWaitlist::new());
+    let chained = Arc::new(ChainWaker(waitlist.clone(), MockWaker::new()));
+    let waker = Waker::from(chained.clone());
+    let mut first = waitlist.wait();
+    first.set_context(&Context::from_waker(&waker), 2);
+    let next = MockWaker::new();
+    let mut second = wait_with_priority(&waitlist, &next, 1);
+
+    // this would deadlock if the waker were woken with the waitlist locked
+    assert!(waitlist.notify_one());
+    assert_eq!(1, chained.1.notified_count());
+    assert_eq!(1, next.notified_count());
+    assert!(first.finish());
+    assert!(second.finish());
+
+    first.set_context(&Context::from_waker(&waker), 2);
+    assert_eq!(1, waitlist.notify_all());
+    assert_eq!(2, chained.1.notified_count());
+    assert!(first.finish());
+}
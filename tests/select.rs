@@ -0,0 +1,224 @@
+mod mock_waker;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Barrier};
+use std::task::{Context, Wake, Waker};
+use std::thread;
+
+use mock_waker::MockWaker;
+use waitlist::select::SelectToken;
+use waitlist::*;
+
+#[test]
+fn first_waitlist_wins() {
+    let a = Waitlist::new();
+    let b = Waitlist::new();
+    let waker = MockWaker::new();
+    let token = SelectToken::new();
+    token.set_context(&waker.to_context());
+    a.register_select(&token);
+    b.register_select(&token);
+
+    assert!(!token.has_fired());
+    assert!(b.notify_one());
+    assert!(token.has_fired());
+    assert!(token.fired_from(&b));
+    assert!(!token.fired_from(&a));
+    assert_eq!(1, waker.notified_count());
+
+    // the second notification is a no-op for the token
+    assert!(a.notify_one());
+    assert!(token.fired_from(&b));
+    assert_eq!(1, waker.notified_count());
+}
+
+#[test]
+fn simultaneous_fire() {
+    for _ in 0..100 {
+        let a = Waitlist::new();
+        let b = Waitlist::new();
+        let waker = MockWaker::new();
+        let token = SelectToken::new();
+        token.set_context(&waker.to_context());
+        a.register_select(&token);
+        b.register_select(&token);
+
+        let barrier = Barrier::new(2);
+        thread::scope(|s| {
+            for wl in [&a, &b] {
+                let barrier = &barrier;
+                s.spawn(move || {
+                    barrier.wait();
+                    wl.notify_one();
+                });
+            }
+        });
+
+        assert!(token.fired_from(&a) != token.fired_from(&b));
+        assert_eq!(1, waker.notified_count());
+    }
+}
+
+#[test]
+fn guard_deregisters_everywhere() {
+    let waitlists: [Waitlist; 3] = Default::default();
+    let waker = MockWaker::new();
+    {
+        let mut guard = select::SelectGuard::new();
+        guard.token().set_context(&waker.to_context());
+        for wl in &waitlists {
+            guard.register(wl);
+        }
+        assert!(waitlists[1].notify_one());
+        assert!(guard.token().fired_from(&waitlists[1]));
+    }
+
+    // the token was removed from the waitlists that didn't fire
+    assert!(!waitlists[0].notify_one());
+    assert!(!waitlists[2].notify_one());
+
+    // and the notification from the one that did fire was marked as handled
+    let other = MockWaker::new();
+    let mut handle = waitlists[1].wait();
+    handle.set_context(&other.to_context());
+    assert!(waitlists[1].notify_any());
+    assert_eq!(1, other.notified_count());
+    assert_eq!(1, waker.notified_count());
+}
+
+#[test]
+fn deregister_is_idempotent() {
+    let waitlist = Waitlist::new();
+    let token = SelectToken::new();
+    waitlist.deregister_select(&token);
+    waitlist.register_select(&token);
+    waitlist.deregister_select(&token);
+    waitlist.deregister_select(&token);
+    assert!(!waitlist.notify_one());
+}
+
+#[test]
+fn losing_notification_is_passed_on() {
+    let a = Waitlist::new();
+    let b = Waitlist::new();
+    let waker = MockWaker::new();
+    let other = MockWaker::new();
+    let mut handle = b.wait();
+    {
+        let mut guard = select::SelectGuard::new();
+        guard.token().set_context(&waker.to_context());
+        guard.register(&a);
+        guard.register(&b);
+        handle.set_context(&other.to_context());
+
+        assert!(a.notify_one());
+        // this notification goes to the token, which already fired
+        assert!(b.notify_one());
+        assert!(guard.token().fired_from(&a));
+        assert_eq!(1, waker.notified_count());
+        assert_eq!(0, other.notified_count());
+    }
+
+    // deregistering the token from `b` woke the waiter behind it
+    assert_eq!(1, other.notified_count());
+    assert!(handle.finish());
+    assert_eq!(0, b.notified_count());
+    assert_eq!(0, a.notified_count());
+}
+
+#[test]
+fn deregister_from_losing_waitlist_wakes_next() {
+    let a = Waitlist::<u32>::default();
+    let b = Waitlist::<u32>::default();
+    let token = SelectToken::new();
+    a.register_select(&token);
+    b.register_select(&token);
+    let other = MockWaker::new();
+    let mut handle = b.wait();
+    handle.set_context(&other.to_context());
+
+    assert!(a.notify_one());
+    assert_eq!(Ok(()), b.notify_one_with(7));
+    a.deregister_select(&token);
+    assert_eq!(0, other.notified_count());
+
+    // the notification, and its payload, go to the next waiter on `b`
+    b.deregister_select(&token);
+    assert_eq!(1, other.notified_count());
+    assert_eq!(Some(7), handle.take_payload());
+    assert!(handle.finish());
+    assert_eq!(0, b.notified_count());
+}
+
+/// The waker of a selecting task that is polled as soon as it is woken, and so sets the
+/// token's waker and uses the waitlist from inside `wake`.
+struct InlinePoll {
+    token: SelectToken,
+    waitlist: Arc<Waitlist>,
+    polls: AtomicUsize,
+}
+
+impl Wake for InlinePoll {
+    fn wake(self: Arc<Self>) {
+        self.polls.fetch_add(1, Ordering::SeqCst);
+        let waker = Waker::from(self.clone());
+        self.token.set_context(&Context::from_waker(&waker));
+        // this locks the waitlist, even if nothing is waiting on it
+        if !self.waitlist.is_closed() {
+            self.waitlist.notify_one();
+        }
+    }
+}
+
+#[test]
+fn woken_task_can_poll_inline() {
+    let waitlist = Arc::new(Waitlist::new());
+    let task = Arc::new(InlinePoll {
+        token: SelectToken::new(),
+        waitlist: waitlist.clone(),
+        polls: AtomicUsize::new(0),
+    });
+    let waker = Waker::from(task.clone());
+    task.token.set_context(&Context::from_waker(&waker));
+    waitlist.register_select(&task.token);
+
+    // neither the token's waker nor the waitlist is locked while the task runs
+    assert!(waitlist.notify_one());
+    assert_eq!(1, task.polls.load(Ordering::SeqCst));
+    assert!(task.token.fired_from(&waitlist));
+    waitlist.deregister_select(&task.token);
+    assert_eq!(0, waitlist.notified_count());
+}
+
+#[test]
+fn register_on_closed_waitlist_wakes_after_unlocking() {
+    let waitlist = Arc::new(Waitlist::new());
+    drop(waitlist.shutdown());
+    let task = Arc::new(InlinePoll {
+        token: SelectToken::new(),
+        waitlist: waitlist.clone(),
+        polls: AtomicUsize::new(0),
+    });
+    let waker = Waker::from(task.clone());
+    task.token.set_context(&Context::from_waker(&waker));
+
+    waitlist.register_select(&task.token);
+    assert_eq!(1, task.polls.load(Ordering::SeqCst));
+    assert!(task.token.fired_from(&waitlist));
+}
+
+#[test]
+fn moved_waitlist_is_still_recognized() {
+    let waitlist = Waitlist::new();
+    let token = SelectToken::new();
+    let waker = MockWaker::new();
+    token.set_context(&waker.to_context());
+    waitlist.register_select(&token);
+
+    let moved = Box::new(waitlist);
+    assert!(moved.notify_one());
+    assert!(token.fired_from(&moved));
+    moved.deregister_select(&token);
+    assert_eq!(0, moved.notified_count());
+    assert!(moved.is_empty());
+}
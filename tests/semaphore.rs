@@ -0,0 +1,98 @@
+mod mock_waker;
+
+use std::task::Poll;
+
+use mock_waker::{poll, MockWaker};
+use waitlist::semaphore::Semaphore;
+
+#[test]
+fn try_acquire_counts_permits() {
+    let semaphore = Semaphore::new(2);
+    let first = semaphore.try_acquire().unwrap();
+    let _second = semaphore.try_acquire().unwrap();
+    assert!(semaphore.try_acquire().is_none());
+    drop(first);
+    assert_eq!(1, semaphore.available_permits());
+    semaphore.try_acquire().unwrap().forget();
+    assert_eq!(0, semaphore.available_permits());
+}
+
+#[test]
+fn add_permits_wakes_at_most_one_waiter_per_permit() {
+    let semaphore = Semaphore::new(0);
+    let wakers: [MockWaker; 3] = Default::default();
+    let mut futs: Vec<_> = wakers.iter().map(|_| semaphore.acquire()).collect();
+    for (fut, waker) in futs.iter_mut().zip(&wakers) {
+        assert!(poll(fut, waker).is_pending());
+    }
+
+    assert_eq!(1, semaphore.add_permits(1));
+    // the first waiter hasn't taken its permit yet, so it still counts for this one
+    assert_eq!(1, semaphore.add_permits(1));
+    let counts: Vec<_> = wakers.iter().map(|w| w.notified_count()).collect();
+    assert_eq!(vec![1, 1, 0], counts);
+
+    let permits: Vec<_> = futs
+        .iter_mut()
+        .zip(&wakers)
+        .take(2)
+        .map(|(fut, waker)| match poll(fut, waker) {
+            Poll::Ready(permit) => permit,
+            Poll::Pending => panic!("woken waiter didn't get a permit"),
+        })
+        .collect();
+    assert_eq!(0, semaphore.available_permits());
+
+    // releasing a permit wakes the last waiter
+    drop(permits);
+    assert_eq!(1, wakers[2].notified_count());
+    assert!(poll(&mut futs[2], &wakers[2]).is_ready());
+    assert_eq!(2, semaphore.available_permits());
+}
+
+#[test]
+fn leftover_permits_are_kept() {
+    let semaphore = Semaphore::new(0);
+    let waker = MockWaker::new();
+    let mut fut = semaphore.acquire();
+    assert!(poll(&mut fut, &waker).is_pending());
+
+    assert_eq!(1, semaphore.add_permits(3));
+    assert_eq!(3, semaphore.available_permits());
+    let _permit = match poll(&mut fut, &waker) {
+        Poll::Ready(permit) => permit,
+        Poll::Pending => panic!("woken waiter didn't get a permit"),
+    };
+    assert_eq!(2, semaphore.available_permits());
+}
+
+#[test]
+fn woken_waiter_that_lost_its_permit_waits_again() {
+    let semaphore = Semaphore::new(0);
+    let waker = MockWaker::new();
+    let mut fut = semaphore.acquire();
+    assert!(poll(&mut fut, &waker).is_pending());
+
+    assert_eq!(1, semaphore.add_permits(1));
+    let stolen = semaphore.try_acquire().unwrap();
+    assert!(poll(&mut fut, &waker).is_pending());
+    drop(stolen);
+    assert_eq!(2, waker.notified_count());
+    assert!(poll(&mut fut, &waker).is_ready());
+}
+
+#[test]
+fn dropped_waiter_passes_on_its_permit() {
+    let semaphore = Semaphore::new(0);
+    let w1 = MockWaker::new();
+    let w2 = MockWaker::new();
+    let mut f1 = semaphore.acquire();
+    let mut f2 = semaphore.acquire();
+    assert!(poll(&mut f1, &w1).is_pending());
+    assert!(poll(&mut f2, &w2).is_pending());
+
+    assert_eq!(1, semaphore.add_permits(1));
+    drop(f1);
+    assert_eq!(1, w2.notified_count());
+    assert!(poll(&mut f2, &w2).is_ready());
+}
@@ -0,0 +1,170 @@
+//! Tests running waitlist futures on a real executor, with its wakers instead of the mock.
+#![cfg(not(loom))]
+
+use std::cell::{Cell, RefCell};
+use std::future::Future;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll, Waker};
+use std::thread;
+
+use smol::LocalExecutor;
+use waitlist::notify::Notify;
+use waitlist::Waitlist;
+
+#[test]
+fn fifo_wake_order() {
+    let ex = LocalExecutor::new();
+    let notify = Rc::new(Notify::new());
+    let order = Rc::new(RefCell::new(Vec::new()));
+
+    let tasks: Vec<_> = (0..3)
+        .map(|i| {
+            let (notify, order) = (notify.clone(), order.clone());
+            ex.spawn(async move {
+                notify.notified().await;
+                order.borrow_mut().push(i);
+            })
+        })
+        .collect();
+    // poll every task once so that they are all waiting, in the order they were spawned
+    while ex.try_tick() {}
+    assert!(order.borrow().is_empty());
+
+    for expected in 0..3 {
+        notify.notify_one();
+        while ex.try_tick() {}
+        assert_eq!(*order.borrow(), (0..=expected).collect::<Vec<_>>());
+    }
+    smol::block_on(ex.run(join_all(tasks)));
+}
+
+#[test]
+fn wake_from_another_thread() {
+    let notify = Arc::new(Notify::new());
+    let received = Arc::new(AtomicUsize::new(0));
+    let notifier = {
+        let (notify, received) = (notify.clone(), received.clone());
+        thread::spawn(move || {
+            // only one permit is stored, so keep notifying until everything was received
+            while received.load(Ordering::Acquire) < 100 {
+                notify.notify_one();
+                thread::yield_now();
+            }
+        })
+    };
+    smol::block_on(async {
+        for _ in 0..100 {
+            notify.notified().await;
+            received.fetch_add(1, Ordering::Release);
+        }
+    });
+    notifier.join().unwrap();
+}
+
+/// A timer that fires when the test says so, instead of after a duration.
+#[derive(Clone, Default)]
+struct ManualTimer(Rc<(Cell<bool>, RefCell<Option<Waker>>)>);
+
+impl ManualTimer {
+    fn fire(&self) {
+        self.0 .0.set(true);
+        if let Some(waker) = self.0 .1.borrow_mut().take() {
+            waker.wake();
+        }
+    }
+}
+
+impl Future for ManualTimer {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.0 .0.get() {
+            Poll::Ready(())
+        } else {
+            *self.0 .1.borrow_mut() = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
+
+#[test]
+fn timed_out_waiter_hands_off() {
+    let ex = LocalExecutor::new();
+    let waitlist = Rc::new(Waitlist::new());
+    let results = Rc::new(RefCell::new(Vec::new()));
+    let timer = ManualTimer::default();
+
+    let spawn = |name: &'static str, timer: Option<ManualTimer>| {
+        let (waitlist, results) = (waitlist.clone(), results.clone());
+        ex.spawn(async move {
+            let notified = async {
+                waitlist.wait_async().await;
+                true
+            };
+            let notified = match timer {
+                // the timer is polled first, so it wins if both are ready
+                Some(timer) => {
+                    let timed_out = async {
+                        timer.await;
+                        false
+                    };
+                    smol::future::or(timed_out, notified).await
+                }
+                None => notified.await,
+            };
+            results.borrow_mut().push((name, notified));
+        })
+    };
+    let tasks = vec![spawn("first", Some(timer.clone())), spawn("second", None)];
+    while ex.try_tick() {}
+    assert_eq!(2, waitlist.len());
+
+    // the first waiter is notified, but times out before it runs, so the notification
+    // is passed on to the second
+    assert!(waitlist.notify_one());
+    timer.fire();
+    while ex.try_tick() {}
+    assert_eq!(*results.borrow(), [("first", false), ("second", true)]);
+    assert!(waitlist.is_empty());
+    assert_eq!(0, waitlist.notified_count());
+    smol::block_on(ex.run(join_all(tasks)));
+}
+
+#[test]
+fn timeout_without_notification_leaves_others_waiting() {
+    let ex = LocalExecutor::new();
+    let waitlist = Rc::new(Waitlist::new());
+    let timer = ManualTimer::default();
+    let timed_out = {
+        let (waitlist, timer) = (waitlist.clone(), timer.clone());
+        ex.spawn(async move { smol::future::or(waitlist.wait_async(), timer).await })
+    };
+    let woken = Rc::new(Cell::new(false));
+    let other = {
+        let (waitlist, woken) = (waitlist.clone(), woken.clone());
+        ex.spawn(async move {
+            waitlist.wait_async().await;
+            woken.set(true);
+        })
+    };
+    while ex.try_tick() {}
+
+    timer.fire();
+    while ex.try_tick() {}
+    assert!(timed_out.is_finished());
+    assert!(!woken.get());
+    assert_eq!(1, waitlist.len());
+    assert!(waitlist.notify_one());
+    while ex.try_tick() {}
+    assert!(woken.get());
+    smol::block_on(ex.run(join_all(vec![timed_out, other])));
+}
+
+async fn join_all(tasks: Vec<smol::Task<()>>) {
+    for task in tasks {
+        task.await;
+    }
+}
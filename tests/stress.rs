@@ -0,0 +1,105 @@
+mod mock_waker;
+
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use mock_waker::MockWaker;
+use waitlist::{WaitHandle, Waitlist};
+
+const WAITERS: usize = 8;
+const NOTIFIERS: usize = 4;
+const RUN_FOR: Duration = Duration::from_secs(5);
+
+#[derive(Default)]
+struct Counts {
+    /// Calls to `notify_one` and `cancel` that reported waking a task
+    notifications: AtomicUsize,
+    wakes: AtomicUsize,
+}
+
+fn hammer(waitlist: &Waitlist, counts: &Counts, stop: &AtomicBool) {
+    thread::scope(|s| {
+        for id in 0..WAITERS {
+            s.spawn(move || {
+                let waker = MockWaker::new();
+                let mut i = id;
+                while !stop.load(Ordering::Relaxed) {
+                    let mut handle = waitlist.wait();
+                    handle.set_context(&waker.to_context());
+                    thread::yield_now();
+                    handle.set_context(&waker.to_context());
+                    match i % 3 {
+                        0 => {
+                            handle.finish();
+                        }
+                        1 => {
+                            if handle.cancel() {
+                                counts.notifications.fetch_add(1, Ordering::Relaxed);
+                            }
+                        }
+                        _ => {
+                            let key = handle.into_key();
+                            if WaitHandle::from_key(waitlist, key).cancel() {
+                                counts.notifications.fetch_add(1, Ordering::Relaxed);
+                            }
+                        }
+                    }
+                    i += 1;
+                }
+                counts
+                    .wakes
+                    .fetch_add(waker.notified_count(), Ordering::Relaxed);
+            });
+        }
+        for _ in 0..NOTIFIERS {
+            s.spawn(move || {
+                while !stop.load(Ordering::Relaxed) {
+                    if waitlist.notify_one() {
+                        counts.notifications.fetch_add(1, Ordering::Relaxed);
+                    }
+                    thread::yield_now();
+                }
+            });
+        }
+        thread::sleep(RUN_FOR);
+        stop.store(true, Ordering::Relaxed);
+    });
+}
+
+/// Run with `cargo test -- --ignored`.
+#[test]
+#[ignore]
+fn concurrent_stress() {
+    let waitlist = Arc::new(Waitlist::new());
+    let counts = Arc::new(Counts::default());
+    let stop = Arc::new(AtomicBool::new(false));
+    let (done_tx, done_rx) = mpsc::channel();
+    let runner = {
+        let (waitlist, counts, stop) = (waitlist.clone(), counts.clone(), stop.clone());
+        thread::spawn(move || {
+            hammer(&waitlist, &counts, &stop);
+            done_tx.send(()).unwrap();
+        })
+    };
+
+    if let Err(RecvTimeoutError::Timeout) = done_rx.recv_timeout(RUN_FOR * 4) {
+        panic!("stress test deadlocked");
+    }
+    // propagates any panic from the worker threads
+    runner.join().unwrap();
+
+    let notifications = counts.notifications.load(Ordering::Relaxed);
+    let wakes = counts.wakes.load(Ordering::Relaxed);
+    assert!(notifications > 0);
+    assert!(
+        wakes <= notifications,
+        "{} wakes for {} notifications",
+        wakes,
+        notifications
+    );
+    // everything was finished, so nothing is left to notify
+    assert!(!waitlist.notify_one());
+}
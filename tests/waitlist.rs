@@ -13,6 +13,37 @@ fn add_all<'a>(wl: &'a Waitlist, wakers: &[MockWaker]) -> Vec<WaitHandle<'a>> {
     wakers.iter().map(|w| wait_for_waker(wl, w)).collect()
 }
 
+#[test]
+fn send_and_sync() {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Waitlist>();
+    assert_send_sync::<Waitlist<String>>();
+    assert_send_sync::<WaitHandle<'_>>();
+    assert_send_sync::<priority::PriorityWaitlist>();
+    assert_send_sync::<shared::SharedWaitlist>();
+}
+
+#[test]
+fn shared_waitlist() {
+    use waitlist::shared::SharedWaitlist;
+
+    let waitlist = SharedWaitlist::new();
+    let other = waitlist.clone();
+    assert!(SharedWaitlist::ptr_eq(&waitlist, &other));
+    assert!(!SharedWaitlist::ptr_eq(&waitlist, &SharedWaitlist::new()));
+
+    let waker = MockWaker::new();
+    let mut handle = wait_for_waker(&waitlist, &waker);
+    assert!(other.notify_one());
+    assert_eq!(1, waker.notified_count());
+    assert!(handle.finish());
+
+    let bounded: SharedWaitlist = Waitlist::with_fixed_capacity(4).into();
+    assert!(bounded.capacity() >= 4);
+    let with_payloads = SharedWaitlist::<u32>::default();
+    assert!(with_payloads.notify_one_with(7).is_err());
+}
+
 #[test]
 fn fifo_order() {
     const N: usize = 7;
@@ -36,6 +67,74 @@ fn fifo_order() {
     }
 }
 
+#[test]
+fn lifo_order() {
+    const N: usize = 7;
+    let wakers: [MockWaker; N] = Default::default();
+    let waitlist = Waitlist::new_lifo();
+    let _refs = add_all(&waitlist, &wakers);
+
+    for i in 0..N {
+        waitlist.notify_one();
+
+        for (j, w) in wakers.iter().enumerate() {
+            let expected = if j >= N - 1 - i { 1 } else { 0 };
+            assert_eq!(
+                expected,
+                w.notified_count(),
+                "Incorrect notification count for waker {} after notification {}",
+                j,
+                i
+            );
+        }
+    }
+    assert!(!waitlist.notify_one());
+}
+
+#[test]
+fn fifo_order_after_cancelling_from_the_middle() {
+    const N: usize = 64;
+    let wakers: Vec<MockWaker> = (0..N).map(|_| MockWaker::new()).collect();
+    let waitlist = Waitlist::new();
+    let mut handles = add_all(&waitlist, &wakers);
+    // enough removals that the queue has to compact its order
+    for handle in handles.iter_mut().skip(1).step_by(2) {
+        assert!(!handle.cancel());
+    }
+    // updating a waker keeps its place
+    handles[4].set_context(&wakers[4].to_context());
+    assert_eq!(N / 2, waitlist.len());
+
+    for i in (0..N).step_by(2) {
+        assert!(waitlist.notify_one());
+        for (j, w) in wakers.iter().enumerate() {
+            let expected = if j % 2 == 0 && j <= i { 1 } else { 0 };
+            assert_eq!(expected, w.notified_count(), "waker {} after {}", j, i);
+        }
+    }
+    assert!(!waitlist.notify_one());
+}
+
+#[test]
+fn stale_key_completion_doesnt_panic() {
+    let waitlist = Waitlist::new();
+    let waker = MockWaker::new();
+    let key = wait_for_waker(&waitlist, &waker).into_key();
+    assert!(waitlist.notify_one());
+
+    assert!(WaitHandle::from_key(&waitlist, key).finish());
+    // the same wait completed again, in each of the ways that account for a notification
+    assert!(!WaitHandle::from_key(&waitlist, key).finish());
+    assert!(!WaitHandle::from_key(&waitlist, key).cancel());
+    assert!(WaitHandle::from_key(&waitlist, key).try_finish(&mut waker.to_context()));
+    drop(WaitHandle::from_key(&waitlist, key));
+
+    // the waitlist still works as usual afterwards
+    let mut handle = wait_for_waker(&waitlist, &waker);
+    assert!(waitlist.notify_one());
+    assert!(handle.finish());
+}
+
 #[test]
 fn notify_all() {
     const N: usize = 7;
@@ -66,6 +165,44 @@ fn notify_any() {
     assert_eq!(1, w2.notified_count());
 }
 
+#[test]
+fn notify_any_n() {
+    let waitlist = Waitlist::new();
+    let wakers: [MockWaker; 5] = Default::default();
+    let mut handles = add_all(&waitlist, &wakers);
+
+    assert_eq!(2, waitlist.notify_any_n(2));
+    assert_eq!(0, waitlist.notify_any_n(2));
+    // one more is needed to have three outstanding
+    assert_eq!(1, waitlist.notify_any_n(3));
+    assert!(handles[0].finish());
+    assert_eq!(1, waitlist.notify_any_n(3));
+    assert_eq!(
+        [1, 1, 1, 1, 0],
+        wakers.each_ref().map(MockWaker::notified_count)
+    );
+
+    // capped by the number of waiting tasks
+    assert_eq!(1, waitlist.notify_any_n(10));
+    assert_eq!(0, waitlist.notify_any_n(10));
+}
+
+#[test]
+fn notified_count() {
+    let waitlist = Waitlist::new();
+    let wakers: [MockWaker; 3] = Default::default();
+    let mut handles = add_all(&waitlist, &wakers);
+    assert_eq!(0, waitlist.notified_count());
+
+    assert_eq!(2, waitlist.notify_n(2));
+    assert_eq!(2, waitlist.notified_count());
+    assert_eq!(3, waitlist.len() + waitlist.notified_count());
+    assert!(handles[0].finish());
+    handles[1].set_context(&wakers[1].to_context());
+    assert_eq!(0, waitlist.notified_count());
+    assert_eq!(2, waitlist.len());
+}
+
 #[test]
 fn cancel_notifies_next() {
     let w1 = MockWaker::new();
@@ -81,6 +218,51 @@ fn cancel_notifies_next() {
 }
 
 #[test]
+fn finish_and_notify_next() {
+    let waitlist = Waitlist::new();
+    let wakers: [MockWaker; 3] = Default::default();
+    let mut handles = add_all(&waitlist, &wakers);
+
+    // unlike cancel, this wakes the next task even though the first wasn't notified
+    assert!(!handles[0].cancel());
+    handles[0] = wait_for_waker(&waitlist, &wakers[0]);
+    assert!(handles[1].finish_and_notify_next());
+    assert_eq!(1, wakers[2].notified_count());
+    assert_eq!(0, wakers[0].notified_count());
+    assert!(handles[2].finish());
+
+    // a task that never waited can hand off too
+    assert!(waitlist.wait().finish_and_notify_next());
+    assert_eq!(1, wakers[0].notified_count());
+    assert!(!handles[0].finish_and_notify_next());
+    assert!(waitlist.is_empty());
+}
+
+#[test]
+fn is_notified() {
+    let waitlist = Waitlist::new();
+    let wakers: [MockWaker; 2] = Default::default();
+    let mut handles = add_all(&waitlist, &wakers);
+    assert!(!waitlist.wait().is_notified());
+    assert!(!handles[0].is_notified());
+
+    assert!(waitlist.notify_one());
+    assert!(handles[0].is_notified());
+    assert!(!handles[1].is_notified());
+    // checking doesn't handle the notification
+    assert!(handles[0].is_notified());
+    assert_eq!(1, waitlist.notified_count());
+    assert!(!waitlist.notify_any());
+
+    // a wait removed by a cancel token wasn't notified
+    assert!(handles[1].cancel_token().cancel());
+    assert!(!handles[1].is_notified());
+    assert!(handles[0].finish());
+    assert!(!handles[0].is_notified());
+}
+
+#[test]
+#[allow(clippy::unnecessary_mut_passed)]
 fn try_finish_works() {
     let waitlist = Waitlist::new();
     let waker = MockWaker::new();
@@ -98,6 +280,24 @@ fn try_finish_works() {
     assert!(waiter.try_finish(&mut cx));
 }
 
+#[test]
+fn poll_finished() {
+    use std::task::Poll;
+
+    let waitlist = Waitlist::new();
+    let (old, new) = (MockWaker::new(), MockWaker::new());
+    let mut handle = wait_for_waker(&waitlist, &old);
+
+    // a pending poll replaces the registered waker
+    assert_eq!(Poll::Pending, handle.poll_finished(&mut new.to_context()));
+    assert!(waitlist.notify_one());
+    assert_eq!(0, old.notified_count());
+    assert_eq!(1, new.notified_count());
+    assert_eq!(Poll::Ready(()), handle.poll_finished(&mut new.to_context()));
+    assert!(!handle.is_pending());
+    assert_eq!(0, waitlist.notified_count());
+}
+
 #[test]
 fn notify_after_clearing() {
     let waitlist = Waitlist::new();
@@ -120,6 +320,98 @@ fn notify_after_clearing() {
     assert_eq!(2, w2.notified_count());
 }
 
+#[test]
+fn fair_requeue_keeps_place() {
+    let waitlist = Waitlist::with_capacity_and_mode(0, WaitlistMode::FairRequeue);
+    let wakers: [MockWaker; 4] = Default::default();
+    let mut handles = add_all(&waitlist, &wakers[..2]);
+    let keys: Vec<_> = handles.iter().map(WaitHandle::key).collect();
+    assert_eq!(2, waitlist.notify_all_count());
+
+    // a task arrives while the notified ones are being polled
+    let _late = wait_for_waker(&waitlist, &wakers[2]);
+    // the notified tasks wait again, in the opposite order, and keep their keys
+    handles[1].set_context(&wakers[1].to_context());
+    handles[0].set_context(&wakers[0].to_context());
+    assert_eq!(
+        keys,
+        handles.iter().map(WaitHandle::key).collect::<Vec<_>>()
+    );
+    assert_eq!(0, waitlist.notified_count());
+    let _later = wait_for_waker(&waitlist, &wakers[3]);
+
+    for (i, expected) in [[2, 1, 0, 0], [2, 2, 0, 0], [2, 2, 1, 0], [2, 2, 1, 1]]
+        .iter()
+        .enumerate()
+    {
+        assert!(waitlist.notify_one());
+        assert_eq!(
+            expected,
+            &wakers.each_ref().map(MockWaker::notified_count),
+            "after notification {}",
+            i
+        );
+    }
+
+    // a task that finished goes to the back like any new one
+    assert!(handles[0].finish());
+    handles[0].set_context(&wakers[0].to_context());
+    assert_ne!(keys[0], handles[0].key());
+}
+
+#[test]
+fn set_context_front() {
+    let waitlist = Waitlist::new();
+    let wakers: [MockWaker; 4] = Default::default();
+    let mut handles = add_all(&waitlist, &wakers[..3]);
+    let mut retry = waitlist.wait();
+    retry.set_context_front(&wakers[3].to_context());
+    // moving a waiting task to the front keeps its key
+    let key = handles[2].key();
+    handles[2].set_context_front(&wakers[2].to_context());
+    assert_eq!(key, handles[2].key());
+
+    let order: Vec<_> = waitlist.keys().into_iter().map(Some).collect();
+    assert_eq!(
+        vec![
+            handles[2].key(),
+            retry.key(),
+            handles[0].key(),
+            handles[1].key()
+        ],
+        order
+    );
+    assert!(!handles[0].cancel());
+    assert_eq!(3, waitlist.notify_all_count());
+    assert_eq!(
+        [0, 1, 1, 1],
+        wakers.each_ref().map(MockWaker::notified_count)
+    );
+    assert!(retry.finish());
+    assert!(handles[1].finish());
+    assert!(handles[2].finish());
+    assert_eq!(0, waitlist.notified_count());
+
+    let full = Waitlist::with_fixed_capacity(1);
+    let _waiting = wait_for_waker(&full, &wakers[0]);
+    let mut handle = full.wait();
+    assert_eq!(
+        Err(WaitlistFull),
+        handle.try_set_context_front(&wakers[1].to_context())
+    );
+    assert!(!handle.is_pending());
+
+    // tasks that jumped the queue still count as registered after a marker
+    let waitlist = Waitlist::new();
+    let mut old = wait_for_waker(&waitlist, &wakers[0]);
+    let marker = waitlist.marker();
+    let mut new = waitlist.wait();
+    new.set_context_front(&wakers[1].to_context());
+    assert_eq!(1, waitlist.notify_since(marker));
+    assert!(new.finish());
+    assert!(!old.finish());
+}
+
 #[test]
 fn update() {
     let waitlist = Waitlist::new();
@@ -141,3 +433,1455 @@ fn update() {
     waitlist.notify_all();
     assert_eq!(2, w2.notified_count());
 }
+
+#[test]
+fn max_waiters_rejects_overflow() {
+    let waitlist = Waitlist::with_max_waiters(1 << 20);
+    assert!(waitlist.capacity() < 1 << 20);
+    let wakers: [MockWaker; 2] = Default::default();
+    let _first = wait_for_waker(&waitlist, &wakers[0]);
+
+    let small = Waitlist::with_max_waiters(1);
+    let mut first = small.wait();
+    assert_eq!(Ok(()), first.try_set_context(&wakers[0].to_context()));
+    let mut second = small.wait();
+    assert_eq!(
+        Err(WaitlistFull),
+        second.try_set_context(&wakers[1].to_context())
+    );
+    assert!(small.notify_one());
+    // a notified task no longer takes up room
+    assert_eq!(Ok(()), second.try_set_context(&wakers[1].to_context()));
+    assert!(first.finish());
+}
+
+#[test]
+fn fixed_capacity_rejects_overflow() {
+    const CAP: usize = 3;
+    let waitlist = Waitlist::with_fixed_capacity(CAP);
+    let wakers: [MockWaker; CAP + 1] = Default::default();
+    let mut handles: Vec<_> = wakers.iter().map(|_| waitlist.wait()).collect();
+
+    for (h, w) in handles.iter_mut().zip(&wakers).take(CAP) {
+        assert_eq!(Ok(()), h.try_set_context(&w.to_context()));
+    }
+    assert_eq!(
+        Err(WaitlistFull),
+        handles[CAP].try_set_context(&wakers[CAP].to_context())
+    );
+    assert!(!handles[CAP].is_pending());
+
+    // updating a waiting task doesn't need more room
+    assert_eq!(Ok(()), handles[0].try_set_context(&wakers[0].to_context()));
+
+    handles[1].finish();
+    assert_eq!(
+        Ok(()),
+        handles[CAP].try_set_context(&wakers[CAP].to_context())
+    );
+}
+
+#[test]
+fn fair_under_drop_churn() {
+    const N: usize = 16;
+    const ROUNDS: usize = 10_000;
+    let waitlist = Waitlist::new();
+    let wakers: [MockWaker; N] = Default::default();
+    let mut handles: Vec<_> = wakers
+        .iter()
+        .map(|w| wait_for_waker(&waitlist, w))
+        .collect();
+    let mut seen = [0; N];
+    // whether each handle has been woken, but hasn't re-registered yet
+    let mut woken = [false; N];
+    // number of notify_one calls since each handle was last registered
+    let mut waited = [0; N];
+    let mut rng: u32 = 0x2545_f491;
+
+    for _ in 0..ROUNDS {
+        rng = rng.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+        let slot = (rng >> 16) as usize % N;
+        match rng & 7 {
+            0 | 1 => {
+                // replace a handle, cancelling the old one if it hasn't finished yet
+                handles[slot] = wait_for_waker(&waitlist, &wakers[slot]);
+                woken[slot] = false;
+                waited[slot] = 0;
+            }
+            2 if woken[slot] => {
+                handles[slot].finish();
+                handles[slot].set_context(&wakers[slot].to_context());
+                woken[slot] = false;
+                waited[slot] = 0;
+            }
+            _ => {
+                waitlist.notify_one();
+                for (w, &woken) in waited.iter_mut().zip(&woken) {
+                    if !woken {
+                        *w += 1;
+                    }
+                }
+            }
+        }
+
+        for i in 0..N {
+            let count = wakers[i].notified_count();
+            if count != seen[i] {
+                seen[i] = count;
+                woken[i] = true;
+            }
+            assert!(
+                woken[i] || waited[i] <= N,
+                "handle {} waited for {} notifications",
+                i,
+                waited[i]
+            );
+        }
+    }
+}
+
+#[test]
+fn try_finish_all() {
+    let waitlist = Waitlist::new();
+    let waker = MockWaker::new();
+    let mut cx = waker.to_context();
+    let mut handles: Vec<_> = (0..4).map(|_| wait_for_waker(&waitlist, &waker)).collect();
+
+    assert_eq!(0, waitlist.try_finish_all(&mut handles, &mut cx));
+    waitlist.notify_one();
+    waitlist.notify_one();
+    assert_eq!(2, waitlist.try_finish_all(&mut handles, &mut cx));
+    assert!(!handles[0].is_pending());
+    assert!(!handles[1].is_pending());
+    assert!(handles[2].is_pending());
+    assert!(handles[3].is_pending());
+
+    // finished handles stay finished, and the rest are still queued
+    assert_eq!(2, waitlist.try_finish_all(&mut handles, &mut cx));
+    assert!(waitlist.notify_one());
+    assert!(waitlist.notify_one());
+    assert!(!waitlist.notify_one());
+    assert_eq!(4, waitlist.try_finish_all(&mut handles, &mut cx));
+
+    // all notifications were handled, so notify_any wakes a new waiter
+    let other = MockWaker::new();
+    let _h = wait_for_waker(&waitlist, &other);
+    assert!(waitlist.notify_any());
+    assert_eq!(1, other.notified_count());
+}
+
+#[test]
+fn notify_one_via_sink() {
+    let waitlist = Waitlist::new();
+    let w1 = MockWaker::new();
+    let w2 = MockWaker::new();
+    let mut k1 = wait_for_waker(&waitlist, &w1);
+    let _k2 = wait_for_waker(&waitlist, &w2);
+
+    let handed_out = std::cell::RefCell::new(Vec::new());
+    assert!(waitlist.notify_one_via(&|w| handed_out.borrow_mut().push(w)));
+    assert_eq!(1, handed_out.borrow().len());
+    assert_eq!(0, w1.notified_count());
+    // the waiter counts as notified even though it hasn't been woken yet
+    assert!(!waitlist.notify_any());
+
+    handed_out.borrow_mut().pop().unwrap().wake();
+    assert_eq!(1, w1.notified_count());
+    assert!(k1.finish());
+    assert!(waitlist.notify_any());
+    assert_eq!(1, w2.notified_count());
+    assert!(!waitlist.notify_one_via(&|_| panic!("queue should be empty")));
+}
+
+#[test]
+fn notify_one_by_ref() {
+    let waitlist = Waitlist::new();
+    assert!(!waitlist.notify_one_by_ref());
+    let wakers: [MockWaker; 2] = Default::default();
+    let mut handles = add_all(&waitlist, &wakers);
+
+    assert!(waitlist.notify_one_by_ref());
+    assert!(waitlist.notify_one_by_ref());
+    assert_eq!(2, wakers[0].notified_count());
+    assert_eq!(0, wakers[1].notified_count());
+    // the woken task is still waiting, and keeps its place when it registers again
+    assert_eq!(2, waitlist.len());
+    handles[0].set_context(&wakers[0].to_context());
+    assert_eq!(handles[0].key(), waitlist.peek());
+    assert!(waitlist.notify_any());
+    assert_eq!(3, wakers[0].notified_count());
+    assert!(handles[0].finish());
+    assert!(!handles[1].finish());
+}
+
+#[test]
+fn notify_one_token() {
+    let waitlist = Waitlist::new();
+    assert_eq!(None, waitlist.notify_one_token());
+    let wakers: [MockWaker; 3] = Default::default();
+    let mut handles: Vec<_> = wakers.iter().map(|_| waitlist.wait()).collect();
+    handles[0].set_context_with_token(&wakers[0].to_context(), 10);
+    handles[1].set_context(&wakers[1].to_context());
+    handles[2].set_context_with_token(&wakers[2].to_context(), 30);
+    // updating the waker keeps the token
+    handles[0].set_context(&wakers[0].to_context());
+
+    assert_eq!(Some(10), waitlist.notify_one_token());
+    assert_eq!(1, wakers[0].notified_count());
+    assert_eq!(Some(0), waitlist.notify_one_token());
+    // a notified task that registers again doesn't keep its token
+    handles[0].set_context(&wakers[0].to_context());
+    assert_eq!(Some(30), waitlist.notify_one_token());
+    assert_eq!(Some(0), waitlist.notify_one_token());
+    assert_eq!(None, waitlist.notify_one_token());
+    assert!(handles.iter_mut().all(|h| h.finish()));
+}
+
+#[test]
+fn notify_where() {
+    let waitlist = Waitlist::new();
+    assert_eq!(0, waitlist.notify_where(|_| true));
+    let shards = [3, 1, 3, 2, 3];
+    let wakers: [MockWaker; 5] = Default::default();
+    let mut handles: Vec<_> = wakers
+        .iter()
+        .zip(shards.iter())
+        .map(|(waker, &shard)| {
+            let mut handle = waitlist.wait();
+            handle.set_context_with_token(&waker.to_context(), shard);
+            handle
+        })
+        .collect();
+
+    assert_eq!(3, waitlist.notify_where(|shard| shard == 3));
+    let counts: Vec<_> = wakers.iter().map(|w| w.notified_count()).collect();
+    assert_eq!(vec![1, 0, 1, 0, 1], counts);
+    assert_eq!(3, waitlist.notified_count());
+    assert_eq!(0, waitlist.notify_where(|shard| shard == 3));
+
+    // the others are still waiting, in their old order
+    assert_eq!(
+        vec![handles[1].key().unwrap(), handles[3].key().unwrap()],
+        waitlist.keys()
+    );
+    assert!(handles[1].is_pending() && !handles[1].is_notified());
+    assert!(waitlist.notify_one());
+    assert_eq!(1, wakers[1].notified_count());
+    assert!(handles
+        .iter_mut()
+        .enumerate()
+        .all(|(i, h)| h.finish() == (i != 3)));
+}
+
+#[test]
+fn notify_all_and_await() {
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::task::Poll;
+
+    let waitlist = Waitlist::new();
+    let wakers: [MockWaker; 3] = Default::default();
+    let mut handles = add_all(&waitlist, &wakers);
+    let coordinator = MockWaker::new();
+    let mut cx = coordinator.to_context();
+
+    let mut all = waitlist.notify_all_and_await();
+    assert!(wakers.iter().all(|w| w.notified_count() == 1));
+    assert_eq!(Poll::Pending, Pin::new(&mut all).poll(&mut cx));
+
+    assert!(handles[0].finish());
+    // re-registering counts as handling the notification
+    handles[1].set_context(&wakers[1].to_context());
+    assert_eq!(0, coordinator.notified_count());
+    assert_eq!(Poll::Pending, Pin::new(&mut all).poll(&mut cx));
+
+    // dropping a notified handle passes the notification on to the re-registered task
+    drop(handles.pop());
+    assert_eq!(2, wakers[1].notified_count());
+    assert_eq!(Poll::Pending, Pin::new(&mut all).poll(&mut cx));
+
+    assert!(handles[1].finish());
+    assert!(coordinator.notified_count() > 0);
+    assert_eq!(Poll::Ready(()), Pin::new(&mut all).poll(&mut cx));
+}
+
+#[test]
+fn set_waker() {
+    let waitlist = Waitlist::new();
+    let (first, second) = (MockWaker::new(), MockWaker::new());
+    let mut handle = waitlist.wait();
+    handle.set_waker(first.to_context().waker().clone());
+    assert!(handle.is_pending());
+    // replacing the waker keeps the task's place
+    let key = handle.key();
+    handle.set_waker(second.to_context().waker().clone());
+    assert_eq!(key, handle.key());
+    assert_eq!(1, waitlist.len());
+
+    assert!(waitlist.notify_one());
+    assert_eq!(0, first.notified_count());
+    assert_eq!(1, second.notified_count());
+    // registering again after the notification takes a new key
+    handle.set_waker(first.to_context().waker().clone());
+    assert_ne!(key, handle.key());
+    assert!(!handle.finish());
+
+    let full = Waitlist::with_fixed_capacity(1);
+    let _waiting = wait_for_waker(&full, &first);
+    let mut handle = full.wait();
+    assert_eq!(
+        Err(WaitlistFull),
+        handle.try_set_waker(second.to_context().waker().clone())
+    );
+}
+
+#[test]
+fn wait_many() {
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+
+    let wakers: [MockWaker; 3] = Default::default();
+    let contexts: Vec<_> = wakers.iter().map(MockWaker::to_context).collect();
+    let waitlist = Waitlist::new();
+    let _first = wait_for_waker(&waitlist, &MockWaker::new());
+    let mut handles = waitlist.wait_many(&contexts);
+    assert!(handles.iter().all(WaitHandle::is_pending));
+    assert_eq!(4, waitlist.len());
+
+    // the batch is queued in order, behind the task that was already waiting
+    assert_eq!(2, waitlist.notify_n(2));
+    assert!(handles[0].finish());
+    assert!(!handles[1].finish());
+    assert_eq!([1, 0, 0], wakers.each_ref().map(MockWaker::notified_count));
+
+    let full = Waitlist::with_fixed_capacity(2);
+    let _waiting = wait_for_waker(&full, &wakers[0]);
+    let batch = AssertUnwindSafe(|| full.wait_many(&contexts[1..]).len());
+    assert!(catch_unwind(batch).is_err());
+    assert_eq!(1, full.len());
+}
+
+#[test]
+fn reset() {
+    let waitlist = Waitlist::new();
+    let (waker, other) = (MockWaker::new(), MockWaker::new());
+    let mut handle = wait_for_waker(&waitlist, &waker);
+    let first = handle.key();
+    assert!(waitlist.notify_one_with(()).is_ok());
+    assert!(handle.finish());
+
+    assert!(!handle.reset());
+    assert!(!handle.is_pending());
+    assert_eq!(None, handle.take_payload());
+    handle.set_context(&waker.to_context());
+    assert!(handle.key().is_some());
+    assert_ne!(first, handle.key());
+
+    // resetting a pending handle cancels its wait, passing on its notification
+    let _next = wait_for_waker(&waitlist, &other);
+    assert!(waitlist.notify_one());
+    assert!(handle.reset());
+    assert_eq!(1, other.notified_count());
+    assert_eq!(1, waitlist.notified_count());
+}
+
+#[test]
+fn key() {
+    let waitlist = Waitlist::new();
+    let waker = MockWaker::new();
+    let mut handle = waitlist.wait();
+    assert_eq!(None, handle.key());
+    handle.set_context(&waker.to_context());
+    let key = handle.key();
+    assert!(key.is_some());
+    // reading the key leaves the handle waiting
+    assert!(handle.is_pending());
+    assert!(waitlist.notify_one());
+    assert_eq!(key, handle.into_key());
+}
+
+#[test]
+fn peek() {
+    let waitlist = Waitlist::new();
+    assert_eq!(None, waitlist.peek());
+    let wakers: [MockWaker; 2] = Default::default();
+    let handles = add_all(&waitlist, &wakers);
+    let first = handles[0].key();
+    assert_eq!(first, waitlist.peek());
+    // peeking doesn't wake anything
+    assert_eq!(first, waitlist.peek());
+    assert_eq!(0, wakers[0].notified_count());
+
+    assert_eq!(first, waitlist.notify_one_key());
+    assert_eq!(handles[1].key(), waitlist.peek());
+}
+
+#[test]
+fn contains() {
+    let waitlist = Waitlist::new();
+    let wakers: [MockWaker; 2] = Default::default();
+    let mut handles = add_all(&waitlist, &wakers);
+    let keys: Vec<_> = handles.iter().map(|h| h.key().unwrap()).collect();
+    assert!(waitlist.contains(keys[0]));
+    assert!(waitlist.contains(keys[1]));
+
+    assert!(waitlist.notify_one());
+    assert!(!waitlist.contains(keys[0]));
+    assert!(!handles[1].cancel());
+    assert!(!waitlist.contains(keys[1]));
+}
+
+#[test]
+fn keys() {
+    let waitlist = Waitlist::new();
+    assert!(waitlist.keys().is_empty());
+    let wakers: [MockWaker; 4] = Default::default();
+    let mut handles = add_all(&waitlist, &wakers);
+    assert!(!handles[1].cancel());
+
+    let keys = waitlist.keys();
+    assert_eq!(3, keys.len());
+    for key in keys {
+        assert_eq!(Some(key), waitlist.notify_one_key());
+    }
+}
+
+#[test]
+fn position() {
+    let waitlist = Waitlist::new();
+    let wakers: [MockWaker; 4] = Default::default();
+    let mut handles = add_all(&waitlist, &wakers);
+    let keys: Vec<_> = handles.iter().map(|h| h.key().unwrap()).collect();
+    for (i, &key) in keys.iter().enumerate() {
+        assert_eq!(Some(i), waitlist.position(key));
+    }
+
+    assert!(waitlist.notify_one());
+    assert!(!handles[2].cancel());
+    assert_eq!(None, waitlist.position(keys[0]));
+    assert_eq!(Some(0), waitlist.position(keys[1]));
+    assert_eq!(None, waitlist.position(keys[2]));
+    assert_eq!(Some(1), waitlist.position(keys[3]));
+}
+
+#[test]
+fn notify_key() {
+    let waitlist = Waitlist::new();
+    let wakers: [MockWaker; 3] = Default::default();
+    let mut handles = add_all(&waitlist, &wakers);
+    let key = handles[1].key().unwrap();
+
+    assert!(waitlist.notify_key(key));
+    assert!(!waitlist.notify_key(key));
+    let counts: Vec<_> = wakers.iter().map(MockWaker::notified_count).collect();
+    assert_eq!(vec![0, 1, 0], counts);
+
+    // the rest are still notified in order
+    assert!(waitlist.notify_one());
+    assert_eq!(1, wakers[0].notified_count());
+    assert!(waitlist.notify_one());
+    assert_eq!(1, wakers[2].notified_count());
+    for handle in &mut handles {
+        assert!(handle.finish());
+    }
+}
+
+#[test]
+fn try_from_key() {
+    let waitlist = Waitlist::new();
+    let waker = MockWaker::new();
+    assert!(WaitHandle::try_from_key(&waitlist, None).is_ok());
+
+    let key = wait_for_waker(&waitlist, &waker).into_key();
+    let handle = WaitHandle::try_from_key(&waitlist, key).unwrap();
+    let key = handle.into_key();
+    assert!(waitlist.notify_one());
+    // a notified key can't be told apart from a stale one
+    assert_eq!(
+        Some(KeyError),
+        WaitHandle::try_from_key(&waitlist, key).err()
+    );
+    assert!(WaitHandle::from_key(&waitlist, key).finish());
+    assert_eq!(
+        Some(KeyError),
+        WaitHandle::try_from_key(&waitlist, key).err()
+    );
+
+    // a key cancelled by a token is accepted once
+    let handle = wait_for_waker(&waitlist, &waker);
+    let token = handle.cancel_token();
+    let key = handle.into_key();
+    assert!(token.cancel());
+    let mut handle = WaitHandle::try_from_key(&waitlist, key).unwrap();
+    assert!(!handle.finish());
+    assert!(WaitHandle::try_from_key(&waitlist, key).is_err());
+}
+
+#[test]
+fn debug_handle() {
+    let waitlist = Waitlist::new();
+    let mut handle = waitlist.wait();
+    assert_eq!(
+        "WaitHandle { key: None, pending: false }",
+        format!("{:?}", handle)
+    );
+    handle.set_context(&MockWaker::new().to_context());
+    assert_eq!(
+        "WaitHandle { key: Some(Key(0)), pending: true }",
+        format!("{:?}", handle)
+    );
+}
+
+#[test]
+fn capacity_and_reserve() {
+    let waitlist = Waitlist::with_capacity(4);
+    assert!(waitlist.capacity() >= 4);
+    let wakers: [MockWaker; 3] = Default::default();
+    let _handles = add_all(&waitlist, &wakers);
+    waitlist.reserve(100);
+    assert!(waitlist.capacity() >= 103);
+}
+
+#[test]
+fn with_expected_waiters() {
+    let waitlist = Waitlist::with_expected_waiters(8);
+    assert!(waitlist.capacity() >= 10);
+    let wakers: Vec<MockWaker> = (0..10).map(|_| MockWaker::new()).collect();
+    let capacity = waitlist.capacity();
+    let _handles = add_all(&waitlist, &wakers);
+    assert_eq!(capacity, waitlist.capacity());
+}
+
+#[test]
+fn shrink_to_fit() {
+    let waitlist = Waitlist::new();
+    let wakers: Vec<MockWaker> = (0..100).map(|_| MockWaker::new()).collect();
+    let mut handles = add_all(&waitlist, &wakers);
+    let grown = waitlist.capacity();
+    assert!(grown >= 100);
+
+    // drain all but the last two waiters
+    for handle in &mut handles[..98] {
+        handle.cancel();
+    }
+    waitlist.shrink_to_fit();
+    assert!(waitlist.capacity() < grown);
+    assert!(waitlist.capacity() >= 2);
+
+    // the remaining waiters keep their order and their handles still work
+    assert!(waitlist.notify_one());
+    assert_eq!(1, wakers[98].notified_count());
+    assert!(handles[98].finish());
+    assert!(!handles[99].finish());
+    assert!(waitlist.is_empty());
+}
+
+#[test]
+fn notify_while() {
+    let waitlist = Waitlist::new();
+    let wakers: [MockWaker; 4] = Default::default();
+    let _handles = add_all(&waitlist, &wakers);
+
+    let mut calls = 0;
+    let woken = waitlist.notify_while(|| {
+        calls += 1;
+        calls <= 2
+    });
+    assert_eq!(2, woken);
+    assert_eq!(3, calls);
+    let counts: Vec<_> = wakers.iter().map(MockWaker::notified_count).collect();
+    assert_eq!(vec![1, 1, 0, 0], counts);
+
+    // stops once the queue is empty, without calling the predicate again
+    let mut calls = 0;
+    assert_eq!(
+        2,
+        waitlist.notify_while(|| {
+            calls += 1;
+            true
+        })
+    );
+    assert_eq!(2, calls);
+    assert_eq!(0, waitlist.notify_while(|| unreachable!()));
+}
+
+#[test]
+fn clear() {
+    let waitlist = Waitlist::new();
+    let wakers: [MockWaker; 3] = Default::default();
+    let mut handles = add_all(&waitlist, &wakers);
+    assert!(waitlist.notify_one());
+
+    waitlist.clear();
+    assert!(waitlist.is_empty());
+    assert!(!waitlist.notify_one());
+    for w in &wakers[1..] {
+        assert_eq!(0, w.notified_count());
+    }
+    assert!(!handles[1].finish());
+    // cancelling a cleared wait doesn't pass anything on to the new waiter
+    let waker = MockWaker::new();
+    let _new = wait_for_waker(&waitlist, &waker);
+    assert!(!handles[2].cancel());
+    assert_eq!(0, waker.notified_count());
+    // the notified task isn't affected
+    assert!(handles[0].finish());
+}
+
+#[test]
+fn drain_wakers() {
+    let waitlist = Waitlist::new();
+    let wakers: [MockWaker; 3] = Default::default();
+    let mut handles = add_all(&waitlist, &wakers);
+    assert!(waitlist.notify_one());
+
+    let drained = waitlist.drain_wakers();
+    assert!(waitlist.is_empty());
+    assert!(waitlist.drain_wakers().is_empty());
+    assert_eq!(2, drained.len());
+    assert!(wakers.iter().skip(1).all(|w| w.notified_count() == 0));
+    for (waker, expected) in drained.into_iter().zip(&wakers[1..]) {
+        assert!(waker.will_wake(expected.to_context().waker()));
+        waker.wake();
+    }
+    assert_eq!([1, 1, 1], wakers.each_ref().map(MockWaker::notified_count));
+
+    // the drained handles are no longer waiting, and don't count as notified
+    assert!(!handles[1].finish());
+    assert!(!handles[2].cancel());
+    assert_eq!(1, waitlist.notified_count());
+    assert!(handles[0].finish());
+    assert_eq!(0, waitlist.notified_count());
+}
+
+#[test]
+fn shutdown() {
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::task::Poll;
+
+    let waitlist = Waitlist::new();
+    let wakers: [MockWaker; 2] = Default::default();
+    let mut handles = add_all(&waitlist, &wakers);
+    let coordinator = MockWaker::new();
+    let mut cx = coordinator.to_context();
+
+    let mut done = waitlist.shutdown();
+    assert!(waitlist.is_closed());
+    assert!(wakers.iter().all(|w| w.notified_count() == 1));
+    assert_eq!(Poll::Pending, Pin::new(&mut done).poll(&mut cx));
+
+    // a notified task trying to wait again is rejected, which also handles its notification
+    handles[0].set_context(&wakers[0].to_context());
+    assert!(!handles[0].is_pending());
+    assert_eq!(2, wakers[0].notified_count());
+
+    // and so is a new registration
+    let late = MockWaker::new();
+    let mut handle = waitlist.wait();
+    handle.set_context(&late.to_context());
+    assert!(!handle.is_pending());
+    assert_eq!(1, late.notified_count());
+    assert!(!waitlist.notify_one());
+
+    assert_eq!(Poll::Pending, Pin::new(&mut done).poll(&mut cx));
+    assert!(handles[1].finish());
+    assert_eq!(Poll::Ready(()), Pin::new(&mut done).poll(&mut cx));
+}
+
+#[test]
+fn notify_adaptive() {
+    let waitlist = Waitlist::new();
+    let wakers: [MockWaker; 16] = Default::default();
+    let _handles = add_all(&waitlist, &wakers);
+
+    // the default policy is the same as notify_one
+    for i in 0..4 {
+        assert_eq!(1, waitlist.notify_adaptive());
+        assert_eq!(1, wakers[i].notified_count());
+        assert_eq!(0, wakers[i + 1].notified_count());
+    }
+
+    waitlist.set_adaptive_policy(AdaptivePolicy {
+        max_batch: 4,
+        threshold: 1,
+    });
+    // pressure has built up while the queue stayed busy
+    assert_eq!(4, waitlist.notify_adaptive());
+    assert_eq!(4, waitlist.notify_adaptive());
+    assert_eq!(4, waitlist.notify_adaptive());
+    assert_eq!(0, waitlist.notify_adaptive());
+    assert!(wakers.iter().all(|w| w.notified_count() == 1));
+}
+
+#[test]
+fn empty_waitlist() {
+    let waitlist = Waitlist::new();
+    assert!(!waitlist.notify_one());
+    assert!(!waitlist.notify_all());
+    assert!(!waitlist.notify_any());
+    assert_eq!(0, waitlist.notify_adaptive());
+
+    let mut handle = waitlist.wait();
+    assert!(!handle.is_pending());
+    assert!(!handle.finish());
+    assert!(!handle.cancel());
+}
+
+#[test]
+fn single_waiter() {
+    let waitlist = Waitlist::new();
+    let waker = MockWaker::new();
+
+    let mut handle = wait_for_waker(&waitlist, &waker);
+    assert!(waitlist.notify_one());
+    assert!(!waitlist.notify_one());
+    assert_eq!(1, waker.notified_count());
+    assert!(handle.finish());
+
+    // cancelling the only waiter has nobody to pass a notification to
+    let mut handle = wait_for_waker(&waitlist, &waker);
+    assert!(!handle.cancel());
+    assert_eq!(1, waker.notified_count());
+    assert!(!waitlist.notify_one());
+
+    let mut handle = wait_for_waker(&waitlist, &waker);
+    assert!(waitlist.notify_one());
+    assert!(!handle.cancel());
+    assert!(!waitlist.notify_any());
+    assert_eq!(2, waker.notified_count());
+}
+
+#[test]
+fn retain_live() {
+    let waitlist = Waitlist::new();
+    let wakers: [MockWaker; 3] = Default::default();
+    let mut handles = add_all(&waitlist, &wakers);
+    assert!(waitlist.notify_one());
+
+    let dead = wakers[1].to_context().waker().clone();
+    assert_eq!(1, waitlist.retain_live(|w| !w.will_wake(&dead)));
+    assert_eq!(0, waitlist.retain_live(|w| !w.will_wake(&dead)));
+    assert_eq!(1, waitlist.len());
+    assert_eq!(1, waitlist.notified_count());
+
+    // dropping the removed task's handle doesn't count as handling the notification
+    drop(handles.remove(1));
+    assert!(!waitlist.notify_any());
+    assert!(handles[0].finish());
+    assert!(waitlist.notify_any());
+    assert_eq!(1, wakers[2].notified_count());
+    assert_eq!(0, wakers[1].notified_count());
+}
+
+/// A clock that only moves when the test advances it.
+#[cfg(feature = "timing")]
+#[derive(Clone)]
+struct ManualClock(std::sync::Arc<std::sync::Mutex<std::time::Instant>>);
+
+#[cfg(feature = "timing")]
+impl ManualClock {
+    fn new() -> ManualClock {
+        ManualClock(std::sync::Arc::new(std::sync::Mutex::new(
+            std::time::Instant::now(),
+        )))
+    }
+
+    fn advance(&self, by: std::time::Duration) {
+        *self.0.lock().unwrap() += by;
+    }
+}
+
+#[cfg(feature = "timing")]
+impl waitlist::clock::Clock for ManualClock {
+    fn now(&self) -> std::time::Instant {
+        *self.0.lock().unwrap()
+    }
+}
+
+#[cfg(feature = "timing")]
+#[test]
+fn gc_stale() {
+    use std::time::Duration;
+
+    let clock = ManualClock::new();
+    let waitlist = Waitlist::with_clock(clock.clone());
+    let waker = MockWaker::new();
+    let abandoned = wait_for_waker(&waitlist, &waker).into_key();
+    let mut live = wait_for_waker(&waitlist, &waker);
+    assert!(abandoned.is_some());
+
+    assert_eq!(0, waitlist.gc_stale(Duration::from_secs(3600)));
+    clock.advance(Duration::from_secs(20));
+    live.set_context(&waker.to_context());
+    assert_eq!(0, waitlist.gc_stale(Duration::from_secs(21)));
+    assert_eq!(1, waitlist.gc_stale(Duration::from_secs(20)));
+
+    // only the live waiter is left
+    assert!(waitlist.notify_one());
+    assert!(!waitlist.notify_one());
+    assert!(live.finish());
+    assert_eq!(1, waker.notified_count());
+}
+
+#[cfg(feature = "timing")]
+#[test]
+fn gc_stale_handle_doesnt_take_notifications() {
+    use std::time::Duration;
+
+    let clock = ManualClock::new();
+    let waitlist = Waitlist::with_clock(clock.clone());
+    let wakers: [MockWaker; 2] = Default::default();
+    let mut live = wait_for_waker(&waitlist, &wakers[0]);
+    let mut collected = wait_for_waker(&waitlist, &wakers[1]);
+    clock.advance(Duration::from_secs(20));
+    live.set_context(&wakers[0].to_context());
+    assert_eq!(1, waitlist.gc_stale(Duration::from_secs(10)));
+
+    assert!(waitlist.notify_one());
+    assert!(!collected.finish());
+    drop(collected);
+    assert_eq!(1, waitlist.notified_count());
+    assert!(live.finish());
+    assert_eq!(0, waitlist.notified_count());
+    assert_eq!(0, wakers[1].notified_count());
+}
+
+#[cfg(feature = "timing")]
+#[test]
+fn oldest_wait_age() {
+    use std::time::Duration;
+
+    let clock = ManualClock::new();
+    let waitlist = Waitlist::with_clock(clock.clone());
+    let waker = MockWaker::new();
+    assert_eq!(None, waitlist.oldest_wait_age());
+
+    let mut first = wait_for_waker(&waitlist, &waker);
+    clock.advance(Duration::from_secs(20));
+    let _second = wait_for_waker(&waitlist, &waker);
+    first.set_context(&waker.to_context());
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(Some(Duration::from_secs(21)), waitlist.oldest_wait_age());
+
+    // once the oldest waiter is notified, the age is that of the next one
+    assert!(waitlist.notify_one());
+    assert_eq!(Some(Duration::from_secs(1)), waitlist.oldest_wait_age());
+    assert!(waitlist.notify_one());
+    assert_eq!(None, waitlist.oldest_wait_age());
+}
+
+#[cfg(feature = "timing")]
+#[test]
+fn front_wait_age_picks_oldest() {
+    use std::time::Duration;
+
+    let clock = ManualClock::new();
+    let waitlists = [
+        Waitlist::with_clock(clock.clone()),
+        Waitlist::with_clock(clock.clone()),
+    ];
+    let wakers: [MockWaker; 2] = Default::default();
+    let _old = wait_for_waker(&waitlists[1], &wakers[1]);
+    clock.advance(Duration::from_secs(10));
+    let _new = wait_for_waker(&waitlists[0], &wakers[0]);
+
+    let oldest = waitlists
+        .iter()
+        .max_by_key(|wl| wl.front_wait_age())
+        .unwrap();
+    assert!(oldest.notify_one());
+    assert_eq!(0, wakers[0].notified_count());
+    assert_eq!(1, wakers[1].notified_count());
+    assert_eq!(None, waitlists[1].front_wait_age());
+    assert_eq!(Some(Duration::from_secs(0)), waitlists[0].front_wait_age());
+    assert_eq!(
+        waitlists[0].oldest_wait_age(),
+        waitlists[0].front_wait_age()
+    );
+}
+
+/// A waker that notifies the next task on the same waitlist when it is woken.
+struct ChainWaker(std::sync::Arc<Waitlist>, MockWaker);
+
+impl std::task::Wake for ChainWaker {
+    fn wake(self: std::sync::Arc<Self>) {
+        self.1.to_context().waker().wake_by_ref();
+        self.0.notify_one();
+    }
+}
+
+#[test]
+fn waker_can_reenter_waitlist() {
+    use std::sync::Arc;
+    use std::task::{Context, Waker};
+
+    let waitlist = Arc::new(Waitlist::new());
+    let chained = Arc::new(ChainWaker(waitlist.clone(), MockWaker::new()));
+    let waker = Waker::from(chained.clone());
+    let mut first = waitlist.wait();
+    first.set_context(&Context::from_waker(&waker));
+    let next = MockWaker::new();
+    let mut second = wait_for_waker(&waitlist, &next);
+
+    // this would deadlock if the waker were woken with the waitlist locked
+    assert!(waitlist.notify_one());
+    assert_eq!(1, chained.1.notified_count());
+    assert_eq!(1, next.notified_count());
+    assert!(first.finish());
+    assert!(second.finish());
+
+    first.set_context(&Context::from_waker(&waker));
+    assert!(waitlist.notify_all());
+    assert_eq!(2, chained.1.notified_count());
+    assert!(first.finish());
+}
+
+/// A waker that registers its task on the waitlist again each time it is woken.
+struct ReregisterWaker {
+    waitlist: std::sync::Arc<Waitlist>,
+    key: std::sync::Mutex<Option<Key>>,
+    wakes: std::sync::atomic::AtomicUsize,
+}
+
+impl std::task::Wake for ReregisterWaker {
+    fn wake(self: std::sync::Arc<Self>) {
+        self.wakes.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        let waker = std::task::Waker::from(self.clone());
+        let mut key = self.key.lock().unwrap();
+        let mut handle = WaitHandle::from_key(&self.waitlist, key.take());
+        handle.set_context(&std::task::Context::from_waker(&waker));
+        *key = handle.into_key();
+    }
+}
+
+#[test]
+fn reregistering_during_notify_all_waits_for_next_notify() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::task::Waker;
+
+    let waitlist = Arc::new(Waitlist::new());
+    let task = Arc::new(ReregisterWaker {
+        waitlist: waitlist.clone(),
+        key: Mutex::new(None),
+        wakes: AtomicUsize::new(0),
+    });
+    let other = MockWaker::new();
+    let mut handle = wait_for_waker(&waitlist, &other);
+    Waker::from(task.clone()).wake();
+    let old_key = task.key.lock().unwrap().unwrap();
+    assert_eq!(1, task.wakes.load(Ordering::SeqCst));
+
+    // the task registers again while it is woken, and isn't woken a second time by the
+    // same notify_all
+    assert!(waitlist.notify_all());
+    assert_eq!(2, task.wakes.load(Ordering::SeqCst));
+    assert_eq!(1, other.notified_count());
+    let new_key = task.key.lock().unwrap().unwrap();
+    assert_ne!(old_key, new_key);
+    assert_eq!(vec![new_key], waitlist.keys());
+    assert!(handle.finish());
+    assert_eq!(0, waitlist.notified_count());
+
+    // it is woken by the next one
+    assert!(waitlist.notify_all());
+    assert_eq!(3, task.wakes.load(Ordering::SeqCst));
+    assert_ne!(new_key, task.key.lock().unwrap().unwrap());
+    assert_eq!(1, waitlist.len());
+}
+
+struct PanickingWaker;
+
+impl std::task::Wake for PanickingWaker {
+    fn wake(self: std::sync::Arc<Self>) {
+        panic!("waker panicked");
+    }
+}
+
+#[test]
+fn panicking_waker_leaves_consistent_state() {
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+    use std::task::{Context, Waker};
+
+    let waitlist = Waitlist::new();
+    let panicking = Waker::from(std::sync::Arc::new(PanickingWaker));
+    let mut k0 = waitlist.wait();
+    k0.set_context(&Context::from_waker(&panicking));
+    let wakers: [MockWaker; 2] = Default::default();
+    let mut handles = add_all(&waitlist, &wakers);
+
+    assert!(catch_unwind(AssertUnwindSafe(|| waitlist.notify_all())).is_err());
+    // every waiter was notified before any was woken, and the others are still woken
+    assert!(wakers.iter().all(|w| w.notified_count() == 1));
+    assert_eq!(3, waitlist.notified_count());
+    assert!(!waitlist.notify_any());
+    assert!(k0.finish());
+    assert!(handles[0].finish());
+    assert!(handles[1].finish());
+    assert_eq!(0, waitlist.notified_count());
+    assert!(!waitlist.notify_one());
+
+    // the waitlist isn't poisoned
+    handles[0].set_context(&wakers[0].to_context());
+    assert!(waitlist.notify_one());
+    assert_eq!(2, wakers[0].notified_count());
+    assert!(handles[0].finish());
+
+    k0.set_context(&Context::from_waker(&panicking));
+    assert!(catch_unwind(AssertUnwindSafe(|| waitlist.notify_one())).is_err());
+    assert!(!waitlist.notify_one());
+    assert!(k0.finish());
+    assert_eq!(1, wakers[1].notified_count());
+}
+
+#[test]
+fn notify_one_key() {
+    let waitlist = Waitlist::new();
+    let wakers: [MockWaker; 3] = Default::default();
+    let keys: Vec<_> = add_all(&waitlist, &wakers)
+        .into_iter()
+        .map(|h| h.into_key().unwrap())
+        .collect();
+
+    for (i, &key) in keys.iter().enumerate() {
+        assert_eq!(Some(key), waitlist.notify_one_key());
+        assert_eq!(1, wakers[i].notified_count());
+        assert!(WaitHandle::from_key(&waitlist, Some(key)).finish());
+    }
+    assert_eq!(None, waitlist.notify_one_key());
+}
+
+#[test]
+fn notify_since_marker() {
+    let waitlist = Waitlist::new();
+    let old: [MockWaker; 2] = Default::default();
+    let new: [MockWaker; 3] = Default::default();
+    let _old_handles = add_all(&waitlist, &old);
+    let marker = waitlist.marker();
+    let mut new_handles = add_all(&waitlist, &new);
+
+    assert_eq!(3, waitlist.notify_since(marker));
+    assert!(new.iter().all(|w| w.notified_count() == 1));
+    assert!(old.iter().all(|w| w.notified_count() == 0));
+    assert_eq!(0, waitlist.notify_since(marker));
+
+    // old waiters are still woken in order
+    for h in &mut new_handles {
+        assert!(h.finish());
+    }
+    assert!(waitlist.notify_any());
+    assert_eq!(1, old[0].notified_count());
+    assert_eq!(0, old[1].notified_count());
+}
+
+#[cfg(feature = "channel")]
+#[test]
+fn subscribe() {
+    let waitlist = Waitlist::new();
+    let (tx, mut rx) = futures_channel::mpsc::unbounded();
+    waitlist.subscribe(tx);
+
+    // subscribers are notified even if no task is waiting
+    assert!(!waitlist.notify_one());
+    assert_eq!(Ok(()), rx.try_recv());
+    assert!(rx.try_recv().is_err());
+
+    // and don't take a notification away from a waiting task
+    let waker = MockWaker::new();
+    let _handle = wait_for_waker(&waitlist, &waker);
+    assert!(waitlist.notify_one());
+    assert_eq!(1, waker.notified_count());
+    assert_eq!(Ok(()), rx.try_recv());
+
+    assert!(!waitlist.notify_all());
+    assert_eq!(Ok(()), rx.try_recv());
+
+    // dropping the receiver unsubscribes
+    drop(rx);
+    assert!(!waitlist.notify_one());
+}
+
+#[cfg(feature = "stream")]
+#[test]
+fn notified_stream() {
+    use futures_core::Stream;
+    use std::pin::Pin;
+    use std::task::Poll;
+
+    let waitlist = Waitlist::new();
+    let waker = MockWaker::new();
+    let mut stream = waitlist.notified_stream();
+    let mut poll = || Pin::new(&mut stream).poll_next(&mut waker.to_context());
+
+    assert_eq!(Poll::Pending, poll());
+    assert_eq!(1, waitlist.len());
+    assert_eq!(Poll::Pending, poll());
+    assert!(waitlist.notify_one());
+    assert_eq!(1, waker.notified_count());
+    // each notification yields once, and the stream is registered again right away
+    assert_eq!(Poll::Ready(Some(())), poll());
+    assert_eq!(1, waitlist.len());
+    assert_eq!(Poll::Pending, poll());
+    assert!(waitlist.notify_one());
+    assert_eq!(Poll::Ready(Some(())), poll());
+
+    drop(waitlist.shutdown());
+    assert_eq!(Poll::Ready(Some(())), poll());
+    assert_eq!(Poll::Ready(None), poll());
+    drop(stream);
+    assert!(waitlist.is_empty());
+}
+
+#[cfg(feature = "stream")]
+#[test]
+fn dropping_notified_stream_passes_on_notification() {
+    use futures_core::Stream;
+    use std::pin::Pin;
+
+    let waitlist = Waitlist::new();
+    let (first, second) = (MockWaker::new(), MockWaker::new());
+    let mut stream = waitlist.notified_stream();
+    assert!(Pin::new(&mut stream)
+        .poll_next(&mut first.to_context())
+        .is_pending());
+    let _handle = wait_for_waker(&waitlist, &second);
+
+    assert!(waitlist.notify_one());
+    drop(stream);
+    assert_eq!(1, second.notified_count());
+}
+
+#[test]
+fn linked_children() {
+    use std::sync::Arc;
+
+    let root = Arc::new(Waitlist::new());
+    let a = Arc::new(Waitlist::new());
+    let b = Arc::new(Waitlist::new());
+    let leaf = Arc::new(Waitlist::new());
+    assert!(root.link_child(a.clone()));
+    assert!(root.link_child(b.clone()));
+    assert!(a.link_child(leaf.clone()));
+
+    // cycles are rejected
+    assert!(!leaf.link_child(root.clone()));
+    assert!(!a.link_child(a.clone()));
+
+    let wakers: [MockWaker; 4] = Default::default();
+    let _handles: Vec<_> = [&root, &a, &b, &leaf]
+        .iter()
+        .zip(&wakers)
+        .map(|(wl, w)| wait_for_waker(wl, w))
+        .collect();
+
+    assert!(a.notify_all());
+    assert_eq!(0, wakers[0].notified_count());
+    assert_eq!(1, wakers[1].notified_count());
+    assert_eq!(0, wakers[2].notified_count());
+    assert_eq!(1, wakers[3].notified_count());
+
+    assert!(root.notify_all());
+    assert!(wakers.iter().all(|w| w.notified_count() == 1));
+    assert!(!root.notify_all());
+}
+
+#[test]
+fn debounce_coalesces() {
+    let waitlist = Waitlist::new();
+    let waker = MockWaker::new();
+    let other = MockWaker::new();
+    let mut handle = waitlist.wait();
+    handle.set_context_debounce(&waker.to_context(), 1);
+    let mut unrelated = waitlist.wait();
+    unrelated.set_context_debounce(&other.to_context(), 2);
+
+    assert!(waitlist.notify_debounce(1));
+    for _ in 0..9 {
+        assert!(!waitlist.notify_debounce(1));
+    }
+    assert_eq!(1, waker.notified_count());
+    assert_eq!(0, other.notified_count());
+    assert!(handle.finish());
+    assert_eq!(10, handle.take_coalesced_count());
+    assert_eq!(0, handle.take_coalesced_count());
+
+    // once the count is taken, the next notification wakes again
+    handle.set_context_debounce(&waker.to_context(), 1);
+    assert!(waitlist.notify_debounce(1));
+    assert_eq!(2, waker.notified_count());
+    assert!(handle.finish());
+
+    // notifications with nobody waiting wake the next task to register
+    assert!(!waitlist.notify_debounce(1));
+    handle.set_context_debounce(&waker.to_context(), 1);
+    assert_eq!(3, waker.notified_count());
+    assert!(handle.finish());
+    assert_eq!(2, handle.take_coalesced_count());
+
+    // the waiter for the other key was never notified
+    assert!(!unrelated.finish());
+    assert_eq!(0, other.notified_count());
+}
+
+#[test]
+fn take_stats() {
+    let waitlist = Waitlist::new();
+    let waker = MockWaker::new();
+    assert_eq!(WaitlistStats::default(), waitlist.take_stats());
+
+    let mut handles: Vec<_> = (0..3).map(|_| wait_for_waker(&waitlist, &waker)).collect();
+    assert!(waitlist.notify_one());
+    assert!(handles[0].finish());
+    assert!(!handles[1].cancel());
+    assert_eq!(
+        WaitlistStats {
+            total_woken: 1,
+            total_inserted: 3,
+            total_cancelled: 1,
+        },
+        waitlist.take_stats()
+    );
+
+    // the counters were reset
+    assert!(waitlist.notify_all());
+    assert_eq!(
+        WaitlistStats {
+            total_woken: 1,
+            total_inserted: 0,
+            total_cancelled: 0,
+        },
+        waitlist.take_stats()
+    );
+    assert!(handles[2].finish());
+}
+
+#[test]
+fn lifetime_stats() {
+    let waitlist = Waitlist::new();
+    let waker = MockWaker::new();
+    assert_eq!(WaitlistTotals::default(), waitlist.stats());
+
+    let mut handles: Vec<_> = (0..3).map(|_| wait_for_waker(&waitlist, &waker)).collect();
+    assert!(waitlist.notify_one());
+    // taking the interval stats doesn't reset the totals
+    waitlist.take_stats();
+    assert!(handles[0].finish());
+    assert_eq!(
+        WaitlistTotals {
+            enqueued_total: 3,
+            woken_total: 1,
+            currently_waiting: 2,
+        },
+        waitlist.stats()
+    );
+
+    assert!(waitlist.notify_all());
+    handles[0].set_context(&waker.to_context());
+    assert_eq!(
+        WaitlistTotals {
+            enqueued_total: 4,
+            woken_total: 3,
+            currently_waiting: 1,
+        },
+        waitlist.stats()
+    );
+}
+
+#[test]
+fn forwarding_waker() {
+    use std::sync::Arc;
+    use std::task::Context;
+
+    let source = Waitlist::new();
+    let target = Arc::new(Waitlist::new());
+    let waker = MockWaker::new();
+    let mut downstream = wait_for_waker(&target, &waker);
+
+    // waking the proxy directly notifies the target
+    let proxy = Waitlist::forwarding_waker(&target);
+    proxy.wake_by_ref();
+    assert_eq!(1, waker.notified_count());
+    assert!(downstream.finish());
+
+    // and so does a notification of a waitlist it is registered on
+    downstream.set_context(&waker.to_context());
+    let mut upstream = source.wait();
+    upstream.set_context(&Context::from_waker(&proxy));
+    assert!(source.notify_one());
+    assert_eq!(2, waker.notified_count());
+    assert!(downstream.finish());
+    assert!(upstream.finish());
+}
+
+#[test]
+fn notify_all_keys() {
+    let waitlist = Waitlist::new();
+    let wakers: [MockWaker; 3] = Default::default();
+    let mut keys: Vec<_> = wakers
+        .iter()
+        .map(|w| wait_for_waker(&waitlist, w).into_key().unwrap())
+        .collect();
+    // a cancelled waiter isn't included
+    assert!(!WaitHandle::from_key(&waitlist, Some(keys.remove(1))).cancel());
+
+    assert_eq!(keys, waitlist.notify_all_keys());
+    assert_eq!([1, 0, 1], wakers.each_ref().map(MockWaker::notified_count));
+    assert_eq!(2, waitlist.notified_count());
+    assert!(waitlist.notify_all_keys().is_empty());
+
+    for key in keys {
+        assert!(WaitHandle::from_key(&waitlist, Some(key)).finish());
+    }
+}
+
+#[test]
+fn notify_n_keys() {
+    let waitlist = Waitlist::new();
+    let wakers: [MockWaker; 3] = Default::default();
+    let keys: Vec<_> = wakers
+        .iter()
+        .map(|w| wait_for_waker(&waitlist, w).into_key().unwrap())
+        .collect();
+
+    assert!(waitlist.notify_n_keys(0).is_empty());
+    assert_eq!(keys[..2], waitlist.notify_n_keys(2)[..]);
+    assert_eq!(1, wakers[1].notified_count());
+    assert_eq!(0, wakers[2].notified_count());
+    // fewer than requested are left
+    assert_eq!(keys[2..], waitlist.notify_n_keys(5)[..]);
+    assert!(waitlist.notify_n_keys(1).is_empty());
+
+    for key in keys {
+        assert!(WaitHandle::from_key(&waitlist, Some(key)).finish());
+    }
+}
+
+#[test]
+fn notify_n() {
+    let waitlist = Waitlist::new();
+    let wakers: [MockWaker; 4] = Default::default();
+    let mut handles = add_all(&waitlist, &wakers);
+
+    assert_eq!(0, waitlist.notify_n(0));
+    assert_eq!(3, waitlist.notify_n(3));
+    let counts: Vec<_> = wakers.iter().map(MockWaker::notified_count).collect();
+    assert_eq!(vec![1, 1, 1, 0], counts);
+
+    // only one waiter is left
+    assert_eq!(1, waitlist.notify_n(5));
+    assert_eq!(0, waitlist.notify_n(5));
+    assert!(handles.iter_mut().all(|h| h.finish()));
+}
+
+#[test]
+fn len() {
+    let waitlist = Waitlist::new();
+    let wakers: [MockWaker; 3] = Default::default();
+    assert_eq!(0, waitlist.len());
+    assert!(waitlist.is_empty());
+    let mut handles = add_all(&waitlist, &wakers);
+    assert_eq!(3, waitlist.len());
+    assert!(!waitlist.is_empty());
+
+    // notified tasks no longer count, even before they finish
+    assert!(waitlist.notify_one());
+    assert_eq!(2, waitlist.len());
+    assert!(!handles[1].finish());
+    assert_eq!(1, waitlist.len());
+    assert!(handles[0].finish());
+    assert_eq!(1, waitlist.len());
+    assert!(!handles[2].finish());
+    assert!(waitlist.is_empty());
+}
+
+#[test]
+fn notify_all_count() {
+    let waitlist = Waitlist::new();
+    let wakers: [MockWaker; 3] = Default::default();
+    assert_eq!(0, waitlist.notify_all_count());
+    let mut handles = add_all(&waitlist, &wakers);
+    assert!(!handles[1].cancel());
+    assert_eq!(2, waitlist.notify_all_count());
+    assert_eq!(0, waitlist.notify_all_count());
+    assert!(handles[0].finish());
+    assert!(handles[2].finish());
+}
+
+#[test]
+fn notify_one_with_payload() {
+    let waitlist = Waitlist::<String>::default();
+    let wakers: [MockWaker; 2] = Default::default();
+    assert_eq!(
+        Err("nobody".to_string()),
+        waitlist.notify_one_with("nobody".into())
+    );
+
+    let mut first = waitlist.wait();
+    first.set_context(&wakers[0].to_context());
+    let mut second = waitlist.wait();
+    second.set_context(&wakers[1].to_context());
+
+    // the payload can be taken before finishing
+    assert_eq!(Ok(()), waitlist.notify_one_with("one".into()));
+    assert_eq!(1, wakers[0].notified_count());
+    assert_eq!(Some("one".to_string()), first.take_payload());
+    assert_eq!(None, first.take_payload());
+    assert!(first.finish());
+
+    // or after
+    first.set_context(&wakers[0].to_context());
+    assert_eq!(Ok(()), waitlist.notify_one_with("two".into()));
+    assert!(second.finish());
+    assert_eq!(Some("two".to_string()), second.take_payload());
+
+    // a plain notification has no payload
+    assert!(waitlist.notify_one());
+    assert!(first.finish());
+    assert_eq!(None, first.take_payload());
+}
+
+#[test]
+fn cancel_forwards_payload() {
+    let waitlist = Waitlist::<u32>::default();
+    let wakers: [MockWaker; 2] = Default::default();
+    let mut handles: Vec<_> = wakers
+        .iter()
+        .map(|w| {
+            let mut handle = waitlist.wait();
+            handle.set_context(&w.to_context());
+            handle
+        })
+        .collect();
+
+    assert_eq!(Ok(()), waitlist.notify_one_with(7));
+    assert!(handles[0].cancel());
+    assert_eq!(None, handles[0].take_payload());
+    assert_eq!(1, wakers[1].notified_count());
+    assert_eq!(Some(7), handles[1].take_payload());
+    assert!(handles[1].finish());
+}
+
+#[test]
+fn wait_async() {
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::task::Poll;
+
+    let waitlist = Waitlist::new();
+    let wakers: [MockWaker; 2] = Default::default();
+    let mut first = waitlist.wait_async();
+    let mut second = waitlist.wait_async();
+    let poll =
+        |fut: &mut WaitFuture<'_>, waker: &MockWaker| Pin::new(fut).poll(&mut waker.to_context());
+    assert_eq!(Poll::Pending, poll(&mut first, &wakers[0]));
+    assert_eq!(Poll::Pending, poll(&mut second, &wakers[1]));
+    assert_eq!(Poll::Pending, poll(&mut first, &wakers[0]));
+
+    assert!(waitlist.notify_one());
+    assert_eq!(1, wakers[0].notified_count());
+    // dropping a notified future passes the notification on
+    drop(first);
+    assert_eq!(1, wakers[1].notified_count());
+    assert_eq!(Poll::Ready(()), poll(&mut second, &wakers[1]));
+    assert!(!waitlist.notify_one());
+}